@@ -0,0 +1,200 @@
+use apollo_compiler::{
+    values::{Directive, OperationDefinition, Selection, Type, Value},
+    ApolloCompiler, ApolloDiagnostic,
+};
+use serde_json::{Number, Value as JsonValue};
+
+pub trait CompilerAdditions {
+    fn operation_by_name(&self, operation_name: Option<&str>) -> Option<OperationDefinition>;
+    fn implemented_interfaces(&self, type_name: &str) -> Vec<String>;
+    fn possible_types(&self, type_name: &str) -> Vec<String>;
+    fn type_directives(&self, type_name: &str) -> Vec<Directive>;
+    fn syntax_errors(&self) -> Vec<String>;
+}
+
+impl CompilerAdditions for ApolloCompiler {
+    fn operation_by_name(&self, operation_name: Option<&str>) -> Option<OperationDefinition> {
+        if let Some(op_name) = operation_name {
+            if let Some(operation) = self
+                .operations()
+                .iter()
+                .find(|op| op.name().unwrap_or_default().eq(op_name))
+            {
+                return Some(operation.clone());
+            }
+        } else if self.operations().len() == 1 {
+            return Some(self.operations().first().expect("qed").clone());
+        }
+
+        None
+    }
+
+    fn implemented_interfaces(&self, type_name: &str) -> Vec<String> {
+        self.object_types()
+            .iter()
+            .find(|ty| ty.name() == type_name)
+            .map(|ty| {
+                ty.implements_interfaces()
+                    .iter()
+                    .map(|i| i.interface().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Concrete object types a value of `type_name` can have at runtime. Empty
+    /// for abstract types without members and for non-composite types.
+    fn possible_types(&self, type_name: &str) -> Vec<String> {
+        if self.object_types().iter().any(|ty| ty.name() == type_name) {
+            return vec![type_name.to_string()];
+        }
+
+        if let Some(union_) = self.unions().iter().find(|ty| ty.name() == type_name) {
+            return union_
+                .union_members()
+                .iter()
+                .map(|member| member.name().to_string())
+                .collect();
+        }
+
+        self.object_types()
+            .iter()
+            .filter(|ty| {
+                ty.implements_interfaces()
+                    .iter()
+                    .any(|i| i.interface() == type_name)
+            })
+            .map(|ty| ty.name().to_string())
+            .collect()
+    }
+
+    /// Directives applied to an object, interface, scalar or enum type.
+    fn type_directives(&self, type_name: &str) -> Vec<Directive> {
+        if let Some(ty) = self.object_types().iter().find(|ty| ty.name() == type_name) {
+            return ty.directives().to_vec();
+        }
+        if let Some(ty) = self.interfaces().iter().find(|ty| ty.name() == type_name) {
+            return ty.directives().to_vec();
+        }
+        if let Some(ty) = self.scalars().iter().find(|ty| ty.name() == type_name) {
+            return ty.directives().to_vec();
+        }
+        if let Some(ty) = self.enums().iter().find(|ty| ty.name() == type_name) {
+            return ty.directives().to_vec();
+        }
+
+        Vec::new()
+    }
+
+    /// Errors from parsing the document. Other validation errors aren't
+    /// included, since operations are validated without the schema.
+    fn syntax_errors(&self) -> Vec<String> {
+        self.validate()
+            .iter()
+            .filter_map(|diagnostic| match diagnostic {
+                ApolloDiagnostic::SyntaxError(err) => Some(err.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub trait TypeAdditions {
+    fn is_list(&self) -> bool;
+}
+
+impl TypeAdditions for Type {
+    fn is_list(&self) -> bool {
+        match self {
+            Type::NonNull { ty, .. } => ty.is_list(),
+            Type::List { .. } => true,
+            Type::Named { .. } => false,
+        }
+    }
+}
+
+pub trait ValueAdditions {
+    fn as_usize(&self) -> Option<usize>;
+    fn as_str_list(&self) -> Option<Vec<String>>;
+    fn to_json(&self) -> Option<JsonValue>;
+}
+
+impl ValueAdditions for Value {
+    fn as_usize(&self) -> Option<usize> {
+        match self {
+            Value::Int(i) => usize::try_from(*i).ok(),
+            _ => None,
+        }
+    }
+
+    fn as_str_list(&self) -> Option<Vec<String>> {
+        match self {
+            Value::List(values) => values
+                .iter()
+                .map(|value| match value {
+                    Value::String(s) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect(),
+            // list input coercion: a single value is a list of one
+            Value::String(s) => Some(vec![s.clone()]),
+            _ => None,
+        }
+    }
+
+    /// Converts a constant value to JSON. Returns `None` if the value contains
+    /// variables.
+    fn to_json(&self) -> Option<JsonValue> {
+        match self {
+            Value::Variable(_) => None,
+            Value::Int(i) => Some(JsonValue::from(*i)),
+            Value::Float(f) => Number::from_f64(f.get()).map(JsonValue::Number),
+            Value::String(s) => Some(JsonValue::String(s.clone())),
+            Value::Boolean(b) => Some(JsonValue::Bool(*b)),
+            Value::Null => Some(JsonValue::Null),
+            Value::Enum(e) => Some(JsonValue::String(e.to_string())),
+            Value::List(values) => values
+                .iter()
+                .map(|value| value.to_json())
+                .collect::<Option<_>>()
+                .map(JsonValue::Array),
+            Value::Object(fields) => fields
+                .iter()
+                .map(|(name, value)| value.to_json().map(|value| (name.to_string(), value)))
+                .collect::<Option<_>>()
+                .map(JsonValue::Object),
+        }
+    }
+}
+
+pub trait DirectivesAdditions {
+    fn argument(&self, directive_name: &str, argument_name: &str) -> Option<&Value>;
+}
+
+impl DirectivesAdditions for [Directive] {
+    fn argument(&self, directive_name: &str, argument_name: &str) -> Option<&Value> {
+        self.iter()
+            .find(|directive| directive.name() == directive_name)
+            .and_then(|directive| {
+                directive
+                    .arguments()
+                    .iter()
+                    .find(|arg| arg.name() == argument_name)
+            })
+            .map(|arg| arg.value())
+    }
+}
+
+pub trait SelectionAdditions {
+    fn directives(&self) -> &[Directive];
+}
+
+impl SelectionAdditions for Selection {
+    fn directives(&self) -> &[Directive] {
+        match self {
+            Selection::Field(f) => f.directives(),
+            Selection::FragmentSpread(f) => f.directives(),
+            Selection::InlineFragment(f) => f.directives(),
+        }
+    }
+}
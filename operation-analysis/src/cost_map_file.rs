@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Contents {
+    /// As in router.yaml, or the output of `cargo xtask cost-map`.
+    Nested {
+        cost_map: HashMap<String, usize>,
+    },
+    Bare(HashMap<String, usize>),
+}
+
+/// Reads a `cost_map_file`, see [`parse`].
+pub fn read(path: &Path) -> Result<HashMap<String, usize>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| anyhow!("could not read {}: {}", path.display(), err))?;
+    parse(path, &contents)
+}
+
+/// Parses a cost map from JSON if `path` ends in `.json`, otherwise from
+/// YAML. The map can be at the top level or under a `cost_map` key.
+pub fn parse(path: &Path, contents: &str) -> Result<HashMap<String, usize>> {
+    let contents: Contents = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => serde_json::from_str(contents)
+            .map_err(|err| anyhow!("could not parse {}: {}", path.display(), err))?,
+        _ => serde_yaml::from_str(contents)
+            .map_err(|err| anyhow!("could not parse {}: {}", path.display(), err))?,
+    };
+
+    Ok(match contents {
+        Contents::Nested { cost_map } | Contents::Bare(cost_map) => cost_map,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use super::parse;
+
+    #[test]
+    fn formats() {
+        let expected = HashMap::from([("Query.topProducts".to_string(), 5)]);
+
+        assert_eq!(
+            parse(Path::new("cost_map.yaml"), "Query.topProducts: 5\n").unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse(
+                Path::new("cost_map.yml"),
+                "---\ncost_map:\n  Query.topProducts: 5\n"
+            )
+            .unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse(Path::new("cost_map.json"), r#"{ "Query.topProducts": 5 }"#).unwrap(),
+            expected
+        );
+        assert!(parse(Path::new("cost_map.json"), "Query.topProducts: 5").is_err());
+        assert!(parse(Path::new("cost_map.yaml"), "Query.topProducts: lots").is_err());
+    }
+}
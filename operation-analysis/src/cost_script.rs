@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context as _, Result};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use serde::Serialize;
+use serde_json::{Map as JsonMap, Value as JsonValue};
+
+/// Name of the function a cost script must define.
+const FIELD_COST_FN: &str = "field_cost";
+
+/// Most operations a single call of the script can run, so a runaway loop
+/// fails the request instead of holding a runtime worker.
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_FUNCTION_EXPR_DEPTH: usize = 32;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_ARRAY_SIZE: usize = 10_000;
+const MAX_MAP_SIZE: usize = 10_000;
+
+/// A Rhai script adjusting the cost of fields, compiled once when loaded.
+///
+/// The script defines `fn field_cost(field)`, called for every costed field
+/// with a [`ScriptField`] as a map. It returns the field's cost as an
+/// integer, or `()` to keep the cost calculated from the configuration.
+pub struct CostScript {
+    engine: Engine,
+    ast: AST,
+}
+
+/// What a cost script gets to know about a field.
+#[derive(Debug, Serialize)]
+pub struct ScriptField<'a> {
+    /// Schema coordinate, e.g. `Query.search`.
+    pub coordinate: &'a str,
+    /// Arguments set on the field, with variables resolved. Arguments whose
+    /// value can't be resolved are left out.
+    pub arguments: JsonMap<String, JsonValue>,
+    /// Every variable of the operation, with defaults applied.
+    #[serde(skip)]
+    pub variables: &'a ScriptVariables,
+    /// Weight charged for the field itself.
+    pub weight: usize,
+    /// Number of times the selections are charged, for lists.
+    pub multiplier: usize,
+    /// Cost of the field's selections for a single item.
+    pub child_cost: usize,
+    /// Cost calculated from the configuration, i.e.
+    /// `weight + multiplier * child_cost`.
+    pub cost: usize,
+}
+
+/// An operation's variables, converted once for all of its fields. Every
+/// field shares the same value, so scripts must not modify it.
+#[derive(Clone)]
+pub struct ScriptVariables(Dynamic);
+
+impl ScriptVariables {
+    pub fn new(variables: &HashMap<String, JsonValue>) -> Result<Self> {
+        Ok(Self(rhai::serde::to_dynamic(variables)?.into_shared()))
+    }
+}
+
+impl std::fmt::Debug for ScriptVariables {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ScriptVariables").finish()
+    }
+}
+
+impl CostScript {
+    pub fn new(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read cost script {}", path.display()))?;
+        Self::from_source(&source)
+            .with_context(|| format!("could not load cost script {}", path.display()))
+    }
+
+    pub fn from_source(source: &str) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_FUNCTION_EXPR_DEPTH)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_ARRAY_SIZE)
+            .set_max_map_size(MAX_MAP_SIZE);
+        let ast = engine.compile(source)?;
+
+        if !ast
+            .iter_functions()
+            .any(|function| function.name == FIELD_COST_FN && function.params.len() == 1)
+        {
+            return Err(anyhow!("missing function {}(field)", FIELD_COST_FN));
+        }
+
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs the script for a field. Returns `None` if the script keeps the
+    /// calculated cost.
+    pub fn field_cost(&self, field: &ScriptField) -> Result<Option<usize>> {
+        let mut map: Map = rhai::serde::to_dynamic(field)?
+            .try_cast()
+            .ok_or_else(|| anyhow!("field did not convert to a map"))?;
+        map.insert("variables".into(), field.variables.0.clone());
+        let field = Dynamic::from(map);
+        let cost: Dynamic =
+            self.engine
+                .call_fn(&mut Scope::new(), &self.ast, FIELD_COST_FN, (field,))?;
+
+        if cost.is::<()>() {
+            return Ok(None);
+        }
+        let cost = cost
+            .as_int()
+            .map_err(|ty| anyhow!("{} returned {} instead of an integer", FIELD_COST_FN, ty))?;
+        usize::try_from(cost)
+            .map(Some)
+            .map_err(|_| anyhow!("{} returned a negative cost {}", FIELD_COST_FN, cost))
+    }
+}
+
+impl std::fmt::Debug for CostScript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CostScript").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+    use serde_json::{json, Map};
+
+    use super::{CostScript, ScriptField, ScriptVariables};
+
+    fn field<'a>(
+        coordinate: &'a str,
+        arguments: Map<String, serde_json::Value>,
+        variables: &'a ScriptVariables,
+    ) -> ScriptField<'a> {
+        ScriptField {
+            coordinate,
+            arguments,
+            variables,
+            weight: 1,
+            multiplier: 10,
+            child_cost: 2,
+            cost: 21,
+        }
+    }
+
+    #[test]
+    fn field_cost() -> Result<()> {
+        let script = CostScript::from_source(
+            r#"
+            fn field_cost(field) {
+                if field.coordinate == "Query.search" {
+                    if "filter" in field.arguments {
+                        return 10 + field.multiplier * field.child_cost;
+                    }
+                    return 50 + field.multiplier * field.child_cost;
+                }
+                if field.variables.expensive == true {
+                    return field.cost * 2;
+                }
+            }
+            "#,
+        )?;
+        let no_variables = ScriptVariables::new(&HashMap::new())?;
+
+        let mut arguments = Map::new();
+        arguments.insert("filter".to_string(), json!({ "name": "a" }));
+        assert_eq!(
+            script.field_cost(&field("Query.search", arguments, &no_variables))?,
+            Some(30)
+        );
+        assert_eq!(
+            script.field_cost(&field("Query.search", Map::new(), &no_variables))?,
+            Some(70)
+        );
+        assert_eq!(
+            script.field_cost(&field("Query.other", Map::new(), &no_variables))?,
+            None
+        );
+
+        let variables =
+            ScriptVariables::new(&HashMap::from([("expensive".to_string(), json!(true))]))?;
+        assert_eq!(
+            script.field_cost(&field("Query.other", Map::new(), &variables))?,
+            Some(42)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_scripts() {
+        assert!(CostScript::from_source("fn field_cost(field) {").is_err());
+        assert!(CostScript::from_source("fn other(field) { 1 }").is_err());
+
+        let script = CostScript::from_source(r#"fn field_cost(field) { "a lot" }"#).unwrap();
+        let variables = ScriptVariables::new(&HashMap::new()).unwrap();
+        assert!(script
+            .field_cost(&field("Query.a", Map::new(), &variables))
+            .is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let variables = ScriptVariables::new(&HashMap::new()).unwrap();
+        for source in [
+            "fn field_cost(field) { loop {} }",
+            "fn field_cost(field) { field_cost(field) }",
+            r#"fn field_cost(field) { let s = "a"; loop { s += s; } }"#,
+        ] {
+            let script = CostScript::from_source(source).unwrap();
+            assert!(script
+                .field_cost(&field("Query.a", Map::new(), &variables))
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::fmt::{self, Display};
+
+/// A problem with the operation itself rather than with analysing it, so the
+/// client can fix it. The analyses return it wrapped in an [`anyhow::Error`];
+/// use `downcast_ref` to tell it apart from other failures.
+#[derive(Debug, PartialEq, Eq)]
+pub enum OperationError {
+    /// The document doesn't parse.
+    Syntax(Vec<String>),
+    /// No operation matches the operation name, or the document has several
+    /// and no name was given.
+    MissingOperation,
+    /// The schema has no root type for the operation's type, e.g. a mutation
+    /// against a schema without mutations.
+    MissingRootType(String),
+    /// A fragment spread names a fragment the document doesn't define.
+    UnknownFragment(String),
+    /// A fragment spreads itself, directly or through other fragments.
+    FragmentCycle(String),
+    /// Analysing the operation would visit too many selections.
+    TooComplex,
+}
+
+impl Display for OperationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationError::Syntax(errors) => write!(f, "syntax error: {}", errors.join("; ")),
+            OperationError::MissingOperation => f.write_str("missing operation"),
+            OperationError::MissingRootType(name) => write!(f, "missing root type {}", name),
+            OperationError::UnknownFragment(name) => write!(f, "unknown fragment {}", name),
+            OperationError::FragmentCycle(name) => write!(f, "fragment {} spreads itself", name),
+            OperationError::TooComplex => f.write_str("operation too complex to analyse"),
+        }
+    }
+}
+
+impl std::error::Error for OperationError {}
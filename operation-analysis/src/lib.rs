@@ -0,0 +1,10 @@
+//! Operation analysis shared by the router plugins and the `xtask` tooling.
+
+pub mod compiler_ext;
+pub mod cost_map_file;
+pub mod cost_script;
+pub mod error;
+pub mod operation_cost;
+pub mod operation_depth;
+pub mod schema;
+pub mod variables;
@@ -0,0 +1,1215 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::Display,
+    ops::{AddAssign, Deref, Mul},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use apollo_compiler::{
+    values::{Field, FragmentDefinition, Selection},
+    ApolloCompiler,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::compiler_ext::{CompilerAdditions, SelectionAdditions};
+use crate::cost_script::{CostScript, ScriptField, ScriptVariables};
+use crate::error::OperationError;
+use crate::schema::{FieldInfo, Schema};
+use crate::variables::Variables;
+
+/// Selections the analysis visits at most before giving up on an operation.
+/// Fragments and selection sets costed before aren't visited again.
+const MAX_VISITED_SELECTIONS: usize = 100_000;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct Cost(usize);
+
+impl Cost {
+    pub fn new(c: usize) -> Self {
+        Self(c)
+    }
+
+    pub fn get(&self) -> usize {
+        self.0
+    }
+}
+
+impl AddAssign for Cost {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 = self.0.saturating_add(rhs.0)
+    }
+}
+
+impl Mul<usize> for Cost {
+    type Output = Cost;
+
+    fn mul(self, rhs: usize) -> Self::Output {
+        Cost(self.0.saturating_mul(rhs))
+    }
+}
+
+impl Display for Cost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How list-returning fields multiply the cost of their selections.
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ListSizeConf {
+    /// Arguments that bound the number of items returned. The largest value
+    /// given on a field is used.
+    pub slicing_arguments: Vec<String>,
+    /// List size used when no slicing argument value or default is available.
+    pub assumed_size: usize,
+}
+
+impl Default for ListSizeConf {
+    fn default() -> Self {
+        Self {
+            slicing_arguments: vec!["first".to_string(), "last".to_string(), "limit".to_string()],
+            assumed_size: 10,
+        }
+    }
+}
+
+struct Context<'a> {
+    compiler: &'a ApolloCompiler,
+    schema: &'a Schema,
+    cost_map: &'a HashMap<String, usize>,
+    list_size: &'a ListSizeConf,
+    /// The script, with the variables it's given for every field.
+    script: Option<(&'a CostScript, ScriptVariables)>,
+    variables: Variables,
+    /// Names of the fragments being expanded, to detect cycles.
+    fragments: RefCell<Vec<String>>,
+    /// Cost of the fragments expanded so far, by name, concrete type and
+    /// the sized fields they're spread with.
+    fragment_costs: RefCell<HashMap<(String, String, Option<SizedFields>), Subtotal>>,
+    /// Cost of the selection sets costed so far, by address, type and sized
+    /// fields, so that the selections below an interface or union aren't
+    /// costed again for each of its possible types.
+    selection_costs: RefCell<HashMap<(usize, String, Option<SizedFields>), Subtotal>>,
+    /// The fragments expanded so far, kept so that the addresses of their
+    /// selection sets aren't reused during the analysis.
+    expanded: RefCell<Vec<Arc<FragmentDefinition>>>,
+    /// Selections visited so far.
+    visited: Cell<usize>,
+}
+
+impl Context<'_> {
+    fn visit(&self) -> Result<()> {
+        let visited = self.visited.get() + 1;
+        if visited > MAX_VISITED_SELECTIONS {
+            return Err(OperationError::TooComplex.into());
+        }
+        self.visited.set(visited);
+        Ok(())
+    }
+}
+
+/// Result of costing an operation.
+#[derive(Clone, Debug)]
+pub struct CostAnalysis {
+    pub cost: Cost,
+    /// Cost charged for each coordinate, most expensive first.
+    pub breakdown: Vec<FieldCost>,
+    /// Variables whose values were read while costing the operation.
+    pub variables: Vec<String>,
+}
+
+impl CostAnalysis {
+    /// The `n` coordinates contributing the most to the cost, most expensive
+    /// first. A field contributes its own weight times the multipliers of
+    /// the lists above it, summed over every place it's selected.
+    pub fn top_coordinates(&self, n: usize) -> Vec<(String, Cost)> {
+        self.breakdown
+            .iter()
+            .take(n)
+            .map(|field| (field.coordinate.clone(), field.total.clone()))
+            .collect()
+    }
+}
+
+/// Cost charged for a schema coordinate, over every place the operation
+/// selects it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FieldCost {
+    /// Schema coordinate, e.g. `Review.author`.
+    pub coordinate: String,
+    /// Weight charged for the field itself.
+    pub weight: usize,
+    /// Number of times the field is charged: once per selection, times the
+    /// sizes of the lists above it.
+    pub count: usize,
+    /// Weight times count.
+    pub total: Cost,
+}
+
+/// Cost of a selection set for a single item, with the weight and count of
+/// each coordinate charged, so repeated selections are merged rather than
+/// listed.
+#[derive(Clone, Debug, Default)]
+struct Subtotal {
+    cost: Cost,
+    fields: HashMap<String, (usize, usize)>,
+}
+
+impl Subtotal {
+    /// Adds the fields of `other`, charged `times` times. Its cost is left
+    /// to the caller, which may adjust it.
+    fn add_fields(&mut self, other: &Subtotal, times: usize) {
+        for (coordinate, (weight, count)) in &other.fields {
+            let entry = self
+                .fields
+                .entry(coordinate.clone())
+                .or_insert((*weight, 0));
+            entry.1 = entry.1.saturating_add(count.saturating_mul(times));
+        }
+    }
+
+    fn add(&mut self, other: &Subtotal) {
+        self.cost += other.cost.clone();
+        self.add_fields(other, 1);
+    }
+
+    fn into_breakdown(self) -> Vec<FieldCost> {
+        let mut breakdown: Vec<_> = self
+            .fields
+            .into_iter()
+            .map(|(coordinate, (weight, count))| FieldCost {
+                coordinate,
+                weight,
+                count,
+                total: Cost(weight) * count,
+            })
+            .collect();
+        breakdown.sort_by(|a, b| {
+            b.total
+                .cmp(&a.total)
+                .then_with(|| a.coordinate.cmp(&b.coordinate))
+        });
+        breakdown
+    }
+}
+
+pub fn operation_cost(
+    schema: &Schema,
+    operation: &str,
+    operation_name: Option<&str>,
+    variables: &Map<String, JsonValue>,
+    cost_map: &HashMap<String, usize>,
+    list_size: &ListSizeConf,
+    script: Option<&CostScript>,
+) -> Result<CostAnalysis> {
+    let compiler = ApolloCompiler::new(operation);
+    let syntax_errors = compiler.syntax_errors();
+    if !syntax_errors.is_empty() {
+        return Err(OperationError::Syntax(syntax_errors).into());
+    }
+
+    match compiler.operation_by_name(operation_name) {
+        Some(operation) => {
+            let variables = Variables::new(&operation, variables);
+            let script = match script {
+                Some(script) => Some((script, ScriptVariables::new(variables.all())?)),
+                None => None,
+            };
+            let context = Context {
+                compiler: &compiler,
+                schema,
+                cost_map,
+                list_size,
+                script,
+                variables,
+                fragments: RefCell::default(),
+                fragment_costs: RefCell::default(),
+                selection_costs: RefCell::default(),
+                expanded: RefCell::default(),
+                visited: Cell::new(0),
+            };
+
+            let parent_name = operation.operation_ty().to_string();
+            if schema.type_info(&parent_name).is_none() {
+                return Err(OperationError::MissingRootType(parent_name).into());
+            }
+
+            let subtotal = selection_set_cost(
+                &context,
+                operation.selection_set().selection(),
+                &parent_name,
+                None,
+            )?;
+
+            Ok(CostAnalysis {
+                cost: subtotal.cost.clone(),
+                breakdown: subtotal.into_breakdown(),
+                variables: context.variables.used(),
+            })
+        }
+        None => Err(OperationError::MissingOperation.into()),
+    }
+}
+
+/// Checks that every `cost_map` entry weights a field of the schema, so a typo
+/// doesn't silently leave the field at its default weight.
+pub fn validate_cost_map(schema: &Schema, cost_map: &HashMap<String, usize>) -> Result<()> {
+    let mut invalid: Vec<_> = cost_map
+        .keys()
+        .filter_map(|coordinate| {
+            schema
+                .check_coordinate(coordinate)
+                .err()
+                .map(|err| format!("{} ({})", coordinate, err))
+        })
+        .collect();
+    invalid.sort();
+
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("invalid cost_map entries: {}", invalid.join(", ")))
+    }
+}
+
+/// List size that `@listSize(sizedFields:)` on a field assigns to some of its
+/// children, e.g. the `edges` of a connection.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct SizedFields {
+    names: Vec<String>,
+    size: usize,
+}
+
+/// Cost of a selection set on `type_name`. For interfaces and unions this is
+/// the most expensive of the possible concrete types, so cost can't be hidden
+/// behind type conditions that don't match the worst case. Only the chosen
+/// type's fields are charged.
+fn selection_set_cost(
+    context: &Context,
+    selection: &[Selection],
+    type_name: &str,
+    sized_fields: Option<&SizedFields>,
+) -> Result<Subtotal> {
+    if selection.is_empty() {
+        return Ok(Subtotal::default());
+    }
+    let key = (
+        selection.as_ptr() as usize,
+        type_name.to_string(),
+        sized_fields.cloned(),
+    );
+    if let Some(subtotal) = context.selection_costs.borrow().get(&key) {
+        return Ok(subtotal.clone());
+    }
+
+    let possible_types = context.schema.possible_types(type_name);
+    let concrete_names = if possible_types.is_empty() {
+        vec![type_name]
+    } else {
+        possible_types.iter().map(String::as_str).collect()
+    };
+
+    let mut worst: Option<Subtotal> = None;
+    for concrete_name in concrete_names {
+        let subtotal =
+            recurse_selections(context, selection, type_name, concrete_name, sized_fields)?;
+        if worst
+            .as_ref()
+            .map_or(true, |worst| subtotal.cost >= worst.cost)
+        {
+            worst = Some(subtotal);
+        }
+    }
+
+    let worst = worst.expect("at least one concrete type");
+    context
+        .selection_costs
+        .borrow_mut()
+        .insert(key, worst.clone());
+    Ok(worst)
+}
+
+fn recurse_selections(
+    context: &Context,
+    selection: &[Selection],
+    parent_name: &str,
+    concrete_name: &str,
+    sized_fields: Option<&SizedFields>,
+) -> Result<Subtotal> {
+    let mut subtotal = Subtotal::default();
+
+    for selection in selection {
+        context.visit()?;
+
+        // ignore selections excluded by @skip or @include
+        if !context.variables.is_included(selection.directives()) {
+            continue;
+        }
+
+        match selection {
+            Selection::Field(f) => {
+                let field_name = f.name();
+
+                // ignore introspection fields
+                if field_name.starts_with("__") {
+                    continue;
+                }
+
+                // fields selected through an interface are weighted by the
+                // interface's entry, or by the concrete type's if only it
+                // has one
+                let (type_name, definition) = match context.schema.field(concrete_name, field_name)
+                {
+                    Some(definition)
+                        if concrete_name != parent_name
+                            && field_weight(context, parent_name, field_name).is_none()
+                            && field_weight(context, concrete_name, field_name).is_some() =>
+                    {
+                        (concrete_name, Some(definition))
+                    }
+                    _ => (parent_name, context.schema.field(parent_name, field_name)),
+                };
+
+                if let Some(definition) = definition {
+                    let coord = format!("{}.{}", type_name, field_name);
+                    let field_cost = field_cost(context, type_name, field_name, definition);
+
+                    let child_sized_fields = definition
+                        .list_size
+                        .as_ref()
+                        .and_then(|list_size| list_size.sized_fields.clone())
+                        .map(|names| SizedFields {
+                            names,
+                            size: list_size(context, f, definition),
+                        });
+                    let multiplier = match sized_fields {
+                        Some(sized) if sized.names.iter().any(|name| name == field_name) => {
+                            sized.size
+                        }
+                        _ if definition.is_list && child_sized_fields.is_none() => {
+                            list_size(context, f, definition)
+                        }
+                        _ => 1,
+                    };
+
+                    tracing::debug!(%coord, %field_cost, %multiplier);
+
+                    let children = selection_set_cost(
+                        context,
+                        f.selection_set().selection(),
+                        &definition.type_name,
+                        child_sized_fields.as_ref(),
+                    )?;
+                    let child_cost = children.cost.clone();
+                    let mut total = Cost(field_cost);
+                    total += child_cost.clone() * multiplier;
+
+                    if let Some((script, script_variables)) = &context.script {
+                        let arguments = f
+                            .arguments()
+                            .iter()
+                            .filter_map(|arg| {
+                                context
+                                    .variables
+                                    .resolve(arg.value())
+                                    .map(|value| (arg.name().to_string(), value))
+                            })
+                            .collect();
+                        let adjusted = script.field_cost(&ScriptField {
+                            coordinate: &coord,
+                            arguments,
+                            variables: script_variables,
+                            weight: field_cost,
+                            multiplier,
+                            child_cost: child_cost.get(),
+                            cost: total.get(),
+                        })?;
+                        if let Some(adjusted) = adjusted {
+                            total = Cost(adjusted);
+                        }
+                    }
+
+                    let entry = subtotal.fields.entry(coord).or_insert((field_cost, 0));
+                    entry.1 = entry.1.saturating_add(1);
+                    subtotal.add_fields(&children, multiplier);
+                    subtotal.cost += total;
+                } else {
+                    tracing::warn!("no type for {}.{}", parent_name, field_name);
+                }
+            }
+            Selection::FragmentSpread(f) => {
+                let fragment = f
+                    .fragment(&context.compiler.db)
+                    .ok_or_else(|| OperationError::UnknownFragment(f.name().to_string()))?;
+
+                let type_condition = fragment.type_condition().to_string();
+                if context
+                    .schema
+                    .type_condition_applies(&type_condition, concrete_name)
+                {
+                    // a fragment spread many times is costed once per
+                    // concrete type
+                    let key = (
+                        f.name().to_string(),
+                        concrete_name.to_string(),
+                        sized_fields.cloned(),
+                    );
+                    if let Some(fragment_cost) = context.fragment_costs.borrow().get(&key) {
+                        subtotal.add(fragment_cost);
+                        continue;
+                    }
+
+                    if context
+                        .fragments
+                        .borrow()
+                        .iter()
+                        .any(|name| name == f.name())
+                    {
+                        return Err(OperationError::FragmentCycle(f.name().to_string()).into());
+                    }
+
+                    context.expanded.borrow_mut().push(fragment.clone());
+                    context.fragments.borrow_mut().push(f.name().to_string());
+                    let fragment_cost = recurse_selections(
+                        context,
+                        fragment.selection_set().selection(),
+                        &type_condition,
+                        concrete_name,
+                        sized_fields,
+                    )?;
+                    context.fragments.borrow_mut().pop();
+                    subtotal.add(&fragment_cost);
+                    context
+                        .fragment_costs
+                        .borrow_mut()
+                        .insert(key, fragment_cost);
+                }
+            }
+            Selection::InlineFragment(f) => {
+                // ... on ConcreteType
+                if let Some(type_condition) = f.type_condition() {
+                    if context
+                        .schema
+                        .type_condition_applies(type_condition, concrete_name)
+                    {
+                        subtotal.add(&recurse_selections(
+                            context,
+                            f.selection_set().selection(),
+                            type_condition,
+                            concrete_name,
+                            sized_fields,
+                        )?);
+                    }
+                // ... @include(if: $x)
+                } else {
+                    subtotal.add(&recurse_selections(
+                        context,
+                        f.selection_set().selection(),
+                        parent_name,
+                        concrete_name,
+                        sized_fields,
+                    )?);
+                }
+            }
+        }
+    }
+
+    Ok(subtotal)
+}
+
+/// Weight of a single field, from the first of:
+///
+/// 1. the `cost_map` entry for the field's coordinate
+/// 2. `@cost(weight:)` on the field definition
+/// 3. either of the above on the same field of an implemented interface
+///    (the highest one wins)
+/// 4. `@cost(weight:)` on the field's return type
+/// 5. a default weight of 1
+fn field_cost(
+    context: &Context,
+    parent_name: &str,
+    field_name: &str,
+    definition: &FieldInfo,
+) -> usize {
+    field_weight(context, parent_name, field_name)
+        .or_else(|| {
+            context
+                .schema
+                .implemented_interfaces(parent_name)
+                .iter()
+                .filter_map(|interface| field_weight(context, interface, field_name))
+                .max()
+        })
+        .or_else(|| {
+            context
+                .schema
+                .type_info(&definition.type_name)
+                .and_then(|ty| ty.weight)
+        })
+        .unwrap_or(1)
+}
+
+fn field_weight(context: &Context, type_name: &str, field_name: &str) -> Option<usize> {
+    let coord = format!("{}.{}", type_name, field_name);
+    context.cost_map.get(coord.deref()).copied().or_else(|| {
+        context
+            .schema
+            .field(type_name, field_name)
+            .and_then(|definition| definition.weight)
+    })
+}
+
+/// Number of items a list field is expected to return: the largest slicing
+/// argument given on the field (resolving variables), else the largest
+/// schema default of a slicing argument, falling back to the assumed size.
+/// `@listSize` on the field definition overrides the configured slicing
+/// arguments and assumed size.
+fn list_size(context: &Context, field: &Field, definition: &FieldInfo) -> usize {
+    let directive = definition.list_size.as_ref();
+    let slicing_arguments = directive
+        .and_then(|list_size| list_size.slicing_arguments.as_ref())
+        .unwrap_or(&context.list_size.slicing_arguments);
+
+    // values given on the field come first, so that a default can't hide a
+    // larger value given for another slicing argument
+    let given = field
+        .arguments()
+        .iter()
+        .filter(|arg| slicing_arguments.iter().any(|name| name == arg.name()))
+        .filter_map(|arg| context.variables.resolve(arg.value()))
+        .filter_map(|value| value.as_u64())
+        .max();
+    let size = given.or_else(|| {
+        slicing_arguments
+            .iter()
+            .filter_map(|name| definition.argument_defaults.get(name))
+            .filter_map(|value| value.as_u64())
+            .max()
+    });
+
+    match size {
+        Some(size) => size as usize,
+        None => directive
+            .and_then(|list_size| list_size.assumed_size)
+            .unwrap_or(context.list_size.assumed_size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+    use serde_json::{json, Map};
+
+    use crate::cost_script::CostScript;
+    use crate::error::OperationError;
+    use crate::operation_cost::{Cost, FieldCost, ListSizeConf};
+    use crate::schema::Schema;
+
+    use super::{operation_cost, validate_cost_map};
+
+    #[test]
+    fn basic() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { hello: String }"),
+            &"{ hello }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("Query.hello".to_string(), 10)]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(10));
+        Ok(())
+    }
+
+    #[test]
+    fn fragments() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { a: A } type A { b: String }"),
+            &"{ a { ...f } } fragment f on A { b }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("Query.a".to_string(), 5), ("A.b".to_string(), 8)]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(13));
+        Ok(())
+    }
+
+    #[test]
+    fn abstract_types() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { a: A } interface A { b: String } type A1 implements A { b: String c: String }"),
+            &"{ a { b ... on A1 { c } } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("Query.a".to_string(), 5), ("A.b".to_string(), 8), ("A1.b".to_string(), 13), ("A1.c".to_string(), 13)]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(26));
+        Ok(())
+    }
+
+    #[test]
+    fn abstract_types_worst_case() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { a: A } interface A { b: String } type A1 implements A { b: String c: String } type A2 implements A { b: String d: String }"),
+            &"{ a { ... on A1 { c } ...f } } fragment f on A2 { d }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("A1.c".to_string(), 3), ("A2.d".to_string(), 20)]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(21));
+        Ok(())
+    }
+
+    #[test]
+    fn interface_cost_applies_to_implementors() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new(
+                "type Query { a: A } interface A { b: String } type A1 implements A { b: String }",
+            ),
+            &"{ a { ... on A1 { b } } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("A.b".to_string(), 7)]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(8));
+        Ok(())
+    }
+
+    #[test]
+    fn implementor_cost_without_interface_cost() -> Result<()> {
+        let schema = Schema::new("type Query { a: A } interface A { b: String } type A1 implements A { b: String } type A2 implements A { b: String }");
+        let analysis = operation_cost(
+            &schema,
+            &"{ a { b } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("A1.b".to_string(), 20)]),
+            &ListSizeConf::default(),
+            None,
+        )?;
+        // A1, the most expensive possible type
+        assert_eq!(analysis.cost, Cost(21));
+        assert_eq!(analysis.breakdown[0].coordinate, "A1.b");
+
+        // the interface's own entry takes precedence
+        let cost = operation_cost(
+            &schema,
+            &"{ a { b } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("A.b".to_string(), 7), ("A1.b".to_string(), 20)]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(8));
+        Ok(())
+    }
+
+    #[test]
+    fn unions() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { u: U } union U = X | Y type X { a: String b: String } type Y { c: String }"),
+            &"{ u { __typename ... on X { a b } ... on Y { c } } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([
+                ("Query.u".to_string(), 2),
+                ("X.a".to_string(), 3),
+                ("X.b".to_string(), 4),
+                ("Y.c".to_string(), 10),
+            ]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(12));
+        Ok(())
+    }
+
+    #[test]
+    fn list_slicing_argument() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { a(first: Int = 5): [A] } type A { b: String }"),
+            &"{ a(first: 3) { b } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("Query.a".to_string(), 2), ("A.b".to_string(), 4)]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(14));
+        Ok(())
+    }
+
+    #[test]
+    fn list_slicing_argument_default() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { a(first: Int = 5): [A] } type A { b: String }"),
+            &"{ a { b } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("Query.a".to_string(), 2), ("A.b".to_string(), 4)]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(22));
+        Ok(())
+    }
+
+    #[test]
+    fn list_slicing_argument_default_and_given() -> Result<()> {
+        let schema =
+            Schema::new("type Query { a(first: Int = 5, last: Int): [A] } type A { b: String }");
+        let cost_map = HashMap::from([("Query.a".to_string(), 2), ("A.b".to_string(), 4)]);
+        let cost = |operation: &str| {
+            operation_cost(
+                &schema,
+                operation,
+                None,
+                &Map::new(),
+                &cost_map,
+                &ListSizeConf::default(),
+                None,
+            )
+            .map(|analysis| analysis.cost)
+        };
+
+        // the default of `first` doesn't hide the given `last`
+        assert_eq!(cost("{ a(last: 1000) { b } }")?, Cost(4002));
+        // several given: the largest
+        assert_eq!(cost("{ a(first: 2, last: 3) { b } }")?, Cost(14));
+        assert_eq!(cost("{ a { b } }")?, Cost(22));
+        Ok(())
+    }
+
+    #[test]
+    fn list_slicing_argument_variable() -> Result<()> {
+        let schema = Schema::new("type Query { a(first: Int = 5): [A] } type A { b: String }");
+        let operation = "query Q($n: Int = 4) { a(first: $n) { b } }".to_string();
+        let cost_map = HashMap::from([("Query.a".to_string(), 2), ("A.b".to_string(), 4)]);
+
+        let mut variables = Map::new();
+        variables.insert("n".to_string(), json!(3));
+        let cost = operation_cost(
+            &schema,
+            &operation,
+            None,
+            &variables,
+            &cost_map,
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(14));
+
+        // falls back to the variable definition default
+        let cost = operation_cost(
+            &schema,
+            &operation,
+            None,
+            &Map::new(),
+            &cost_map,
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(18));
+        Ok(())
+    }
+
+    #[test]
+    fn skip_and_include() -> Result<()> {
+        let mut variables = Map::new();
+        variables.insert("withB".to_string(), json!(false));
+        let cost = operation_cost(
+            &Schema::new("type Query { a: A } type A { b: String c: String d: String }"),
+            &"query Q($withB: Boolean!, $withC: Boolean = true) { a { b @include(if: $withB) ... @include(if: $withC) { c } d @skip(if: true) } }".to_string(),
+            None,
+            &variables,
+            &HashMap::from([
+                ("Query.a".to_string(), 1),
+                ("A.b".to_string(), 10),
+                ("A.c".to_string(), 100),
+                ("A.d".to_string(), 1000),
+            ]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost(101));
+        Ok(())
+    }
+
+    #[test]
+    fn cost_directives() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { a: A @cost(weight: 3) b: B c: String @cost(weight: 7) } type A { x: String } type B @cost(weight: 20) { x: String }"),
+            &"{ a { x } b { x } c }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("Query.c".to_string(), 2)]),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        // a + x + b (from type) + x + c (from cost_map)
+        assert_eq!(cost, Cost(3 + 1 + 20 + 1 + 2));
+        Ok(())
+    }
+
+    #[test]
+    fn list_size_directive() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { a(size: Int): [A] @listSize(slicingArguments: [\"size\"]) b: [A] @listSize(assumedSize: 4) } type A { x: String }"),
+            &"{ a(size: 3) { x } b { x } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::new(),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        assert_eq!(cost, Cost((1 + 3) + (1 + 4)));
+        Ok(())
+    }
+
+    #[test]
+    fn list_size_sized_fields() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { users(first: Int): UserConnection @listSize(slicingArguments: [\"first\"], sizedFields: [\"edges\"]) } type UserConnection { edges: [UserEdge] total: Int } type UserEdge { node: User } type User { name: String }"),
+            &"{ users(first: 5) { total edges { node { name } } } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::new(),
+            &ListSizeConf::default(),
+            None,
+        )?
+        .cost;
+        // users + total + edges + 5 * (node + name)
+        assert_eq!(cost, Cost(1 + 1 + 1 + 5 * 2));
+        Ok(())
+    }
+
+    #[test]
+    fn breakdown() -> Result<()> {
+        let analysis = operation_cost(
+            &Schema::new("type Query { a(first: Int): [A] } type A { b: String c: String }"),
+            &"{ a(first: 2) { b renamed: c } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("Query.a".to_string(), 3), ("A.c".to_string(), 5)]),
+            &ListSizeConf::default(),
+            None,
+        )?;
+        assert_eq!(analysis.cost, Cost(15));
+        assert_eq!(
+            analysis.breakdown,
+            vec![
+                FieldCost {
+                    coordinate: "A.c".to_string(),
+                    weight: 5,
+                    count: 2,
+                    total: Cost(10),
+                },
+                FieldCost {
+                    coordinate: "Query.a".to_string(),
+                    weight: 3,
+                    count: 1,
+                    total: Cost(3),
+                },
+                FieldCost {
+                    coordinate: "A.b".to_string(),
+                    weight: 1,
+                    count: 2,
+                    total: Cost(2),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nested_abstract_types() -> Result<()> {
+        // six levels of an interface with eight possible types, 8^6 ways to
+        // resolve the operation
+        let mut schema = "type Query { i: I } interface I { i: I x: String }".to_string();
+        for t in 0..8 {
+            schema += &format!(" type T{} implements I {{ i: I x: String }}", t);
+        }
+        let mut cost_map = HashMap::new();
+        cost_map.insert("T7.x".to_string(), 10);
+
+        let analysis = operation_cost(
+            &Schema::new(&schema),
+            "{ i { i { i { i { i { i { x } } } } } } }",
+            None,
+            &Map::new(),
+            &cost_map,
+            &ListSizeConf::default(),
+            None,
+        )?;
+        assert_eq!(analysis.cost, Cost(6 + 10));
+        assert_eq!(analysis.breakdown[0].coordinate, "T7.x");
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_fragments() -> Result<()> {
+        // each fragment spreads the next twice, 2^29 times in all
+        let mut operation = "{ ...F0 }".to_string();
+        for i in 0..29 {
+            operation += &format!(
+                " fragment F{} on Query {{ ...F{} ...F{} }}",
+                i,
+                i + 1,
+                i + 1
+            );
+        }
+        operation += " fragment F29 on Query { a }";
+
+        let analysis = operation_cost(
+            &Schema::new("type Query { a: String }"),
+            &operation,
+            None,
+            &Map::new(),
+            &HashMap::new(),
+            &ListSizeConf::default(),
+            None,
+        )?;
+        assert_eq!(analysis.cost, Cost(1 << 29));
+        assert_eq!(
+            analysis.breakdown,
+            vec![FieldCost {
+                coordinate: "Query.a".to_string(),
+                weight: 1,
+                count: 1 << 29,
+                total: Cost(1 << 29),
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn top_coordinates() -> Result<()> {
+        let analysis = operation_cost(
+            &Schema::new("type Query { a(first: Int): [A] } type A { b: String c: String }"),
+            &"{ a(first: 2) { b renamed: c c } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::from([("Query.a".to_string(), 3), ("A.c".to_string(), 5)]),
+            &ListSizeConf::default(),
+            None,
+        )?;
+        assert_eq!(analysis.cost, Cost(25));
+        assert_eq!(
+            analysis.top_coordinates(2),
+            vec![
+                ("A.c".to_string(), Cost(20)),
+                ("Query.a".to_string(), Cost(3)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn operation_errors() {
+        let schema = Schema::new("type Query { a: String }");
+        let error = |operation: &str, operation_name: Option<&str>| {
+            operation_cost(
+                &schema,
+                operation,
+                operation_name,
+                &Map::new(),
+                &HashMap::new(),
+                &ListSizeConf::default(),
+                None,
+            )
+            .unwrap_err()
+            .downcast::<OperationError>()
+            .ok()
+        };
+
+        assert!(matches!(
+            error("{ a", None),
+            Some(OperationError::Syntax(_))
+        ));
+        assert_eq!(
+            error("query A { a } query B { a }", None),
+            Some(OperationError::MissingOperation)
+        );
+        assert_eq!(
+            error("{ a }", Some("A")),
+            Some(OperationError::MissingOperation)
+        );
+        assert_eq!(
+            error("mutation { a }", None),
+            Some(OperationError::MissingRootType("Mutation".to_string()))
+        );
+        assert_eq!(
+            error("{ ...Nope }", None),
+            Some(OperationError::UnknownFragment("Nope".to_string()))
+        );
+        assert_eq!(
+            error(
+                "{ ...A } fragment A on Query { ...B } fragment B on Query { a ...A }",
+                None
+            ),
+            Some(OperationError::FragmentCycle("A".to_string()))
+        );
+        assert_eq!(
+            error(
+                &format!("{{ {} }}", "a ".repeat(super::MAX_VISITED_SELECTIONS + 1)),
+                None
+            ),
+            Some(OperationError::TooComplex)
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let schema = Schema::new("type Query { a: A } type A { b: String } enum E { X }");
+
+        assert!(validate_cost_map(
+            &schema,
+            &HashMap::from([("Query.a".to_string(), 2), ("A.b".to_string(), 1)]),
+        )
+        .is_ok());
+        assert_eq!(
+            validate_cost_map(
+                &schema,
+                &HashMap::from([
+                    ("Query.a".to_string(), 2),
+                    ("Query.b".to_string(), 1),
+                    ("E.X".to_string(), 1),
+                    ("A-b".to_string(), 1),
+                ]),
+            )
+            .unwrap_err()
+            .to_string(),
+            "invalid cost_map entries: A-b (expected Type.field), E.X (enum values have no cost), Query.b (unknown field)"
+        );
+    }
+
+    #[test]
+    fn list_assumed_size() -> Result<()> {
+        let cost = operation_cost(
+            &Schema::new("type Query { a: [A]! } type A { b: [B] } type B { c: String }"),
+            &"{ a { b { c } } }".to_string(),
+            None,
+            &Map::new(),
+            &HashMap::new(),
+            &ListSizeConf {
+                assumed_size: 2,
+                ..Default::default()
+            },
+            None,
+        )?
+        .cost;
+        // a + 2 * (b + 2 * c)
+        assert_eq!(cost, Cost(7));
+        Ok(())
+    }
+
+    #[test]
+    fn script() -> Result<()> {
+        let script = CostScript::from_source(
+            r#"
+            fn field_cost(field) {
+                if field.coordinate == "Query.search" && !("filter" in field.arguments) {
+                    return 50 + field.multiplier * field.child_cost;
+                }
+            }
+            "#,
+        )?;
+        let schema = Schema::new(
+            "type Query { search(filter: String, first: Int): [A] } type A { b: String }",
+        );
+
+        let cost = |operation: &str, variables: serde_json::Value| {
+            operation_cost(
+                &schema,
+                operation,
+                None,
+                variables.as_object().expect("object"),
+                &HashMap::new(),
+                &ListSizeConf::default(),
+                Some(&script),
+            )
+            .map(|analysis| analysis.cost)
+        };
+
+        assert_eq!(cost("{ search(first: 2) { b } }", json!({}))?, Cost(52));
+        assert_eq!(
+            cost(r#"{ search(first: 2, filter: "a") { b } }"#, json!({}))?,
+            Cost(3)
+        );
+        assert_eq!(
+            cost(
+                "query($filter: String) { search(first: 2, filter: $filter) { b } }",
+                json!({ "filter": "a" })
+            )?,
+            Cost(3)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn script_nested_variables() -> Result<()> {
+        let script = CostScript::from_source(
+            r#"
+            fn field_cost(field) {
+                if field.coordinate == "Query.search" && !("name" in field.arguments.filter) {
+                    return 50 + field.multiplier * field.child_cost;
+                }
+            }
+            "#,
+        )?;
+        let schema = Schema::new(
+            "type Query { search(filter: Filter, first: Int): [A] } input Filter { name: String } type A { b: String }",
+        );
+
+        let cost = |variables: serde_json::Value| {
+            operation_cost(
+                &schema,
+                "query($name: String) { search(first: 2, filter: { name: $name }) { b } }",
+                None,
+                variables.as_object().expect("object"),
+                &HashMap::new(),
+                &ListSizeConf::default(),
+                Some(&script),
+            )
+            .map(|analysis| analysis.cost)
+        };
+
+        // the script sees variables inside input objects, and those without
+        // a value leave their field out
+        assert_eq!(cost(json!({ "name": "a" }))?, Cost(3));
+        assert_eq!(cost(json!({}))?, Cost(52));
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use apollo_compiler::{
+    values::{OperationDefinition, Selection},
+    ApolloCompiler,
+};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::compiler_ext::{CompilerAdditions, SelectionAdditions};
+use crate::error::OperationError;
+use crate::variables::Variables;
+
+/// Result of measuring an operation's depth.
+#[derive(Clone, Debug)]
+pub struct DepthAnalysis {
+    pub depth: usize,
+    /// Variables whose values were read while measuring the operation.
+    pub variables: Vec<String>,
+}
+
+pub fn operation_depth(
+    operation: &str,
+    operation_name: Option<&str>,
+    variables: &Map<String, JsonValue>,
+) -> Result<DepthAnalysis> {
+    let compiler = ApolloCompiler::new(operation);
+    let syntax_errors = compiler.syntax_errors();
+    if !syntax_errors.is_empty() {
+        return Err(OperationError::Syntax(syntax_errors).into());
+    }
+
+    match compiler.operation_by_name(operation_name) {
+        Some(operation) => {
+            let variables = Variables::new(&operation, variables);
+            let depth = operation.max_depth(&compiler, &variables)?;
+
+            Ok(DepthAnalysis {
+                depth,
+                variables: variables.used(),
+            })
+        }
+        None => Err(OperationError::MissingOperation.into()),
+    }
+}
+
+pub trait OperationDefinitionExt {
+    fn max_depth(&self, ctx: &ApolloCompiler, variables: &Variables) -> Result<usize>;
+}
+
+impl OperationDefinitionExt for OperationDefinition {
+    fn max_depth(&self, ctx: &ApolloCompiler, variables: &Variables) -> Result<usize> {
+        return recurse_selections(
+            self.selection_set().selection(),
+            0,
+            ctx,
+            variables,
+            &mut Fragments::default(),
+        );
+    }
+}
+
+/// Fragments met while measuring an operation.
+#[derive(Default)]
+struct Fragments {
+    /// Names of the fragments being expanded, to detect cycles.
+    expanding: Vec<String>,
+    /// Depth of each fragment measured so far, below where it's spread, so
+    /// a fragment spread many times is only measured once.
+    depths: HashMap<String, usize>,
+}
+
+fn recurse_selections(
+    selections: &[Selection],
+    depth: usize,
+    ctx: &ApolloCompiler,
+    variables: &Variables,
+    fragments: &mut Fragments,
+) -> Result<usize> {
+    let mut max_depth = depth;
+
+    for selection in selections {
+        // ignore selections excluded by @skip or @include
+        if !variables.is_included(selection.directives()) {
+            continue;
+        }
+
+        match selection {
+            Selection::Field(f) => {
+                let new_depth = recurse_selections(
+                    f.selection_set().selection(),
+                    depth + 1,
+                    ctx,
+                    variables,
+                    fragments,
+                )?;
+                if new_depth > max_depth {
+                    max_depth = new_depth
+                }
+            }
+            Selection::FragmentSpread(f) => {
+                let fragment_depth = match fragments.depths.get(f.name()) {
+                    Some(fragment_depth) => *fragment_depth,
+                    None => {
+                        let fragment = f
+                            .fragment(&ctx.db)
+                            .ok_or_else(|| OperationError::UnknownFragment(f.name().to_string()))?;
+                        if fragments.expanding.iter().any(|name| name == f.name()) {
+                            return Err(OperationError::FragmentCycle(f.name().to_string()).into());
+                        }
+
+                        fragments.expanding.push(f.name().to_string());
+                        let fragment_depth = recurse_selections(
+                            fragment.selection_set().selection(),
+                            0,
+                            ctx,
+                            variables,
+                            fragments,
+                        )?;
+                        fragments.expanding.pop();
+                        fragments
+                            .depths
+                            .insert(f.name().to_string(), fragment_depth);
+                        fragment_depth
+                    }
+                };
+                let new_depth = depth + fragment_depth;
+                if new_depth > max_depth {
+                    max_depth = new_depth
+                }
+            }
+            Selection::InlineFragment(f) => {
+                let new_depth = recurse_selections(
+                    f.selection_set().selection(),
+                    depth,
+                    ctx,
+                    variables,
+                    fragments,
+                )?;
+                if new_depth > max_depth {
+                    max_depth = new_depth
+                }
+            }
+        }
+    }
+
+    Ok(max_depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::OperationError;
+    use crate::operation_depth::OperationDefinitionExt;
+    use crate::variables::Variables;
+
+    use apollo_compiler::ApolloCompiler;
+    use serde_json::{json, Map};
+
+    #[test]
+    fn basic() {
+        let ctx = ApolloCompiler::new(&String::from("{ hello { world } }"));
+        let operations = ctx.operations();
+        let operation = operations.first().expect("operation missing");
+        let depth = operation.max_depth(&ctx, &Variables::default()).unwrap();
+        assert_eq!(depth, 2);
+    }
+
+    #[test]
+    fn inline_fragments() {
+        let op = &String::from(
+            "
+{
+  a {
+    ... on B {
+      c
+      d {
+        e
+      }
+    }
+  }
+}",
+        );
+        let ctx = ApolloCompiler::new(op);
+        let operations = ctx.operations();
+        let operation = operations.first().expect("operation missing");
+        let depth = operation.max_depth(&ctx, &Variables::default()).unwrap();
+        assert_eq!(depth, 3);
+    }
+
+    #[test]
+    fn named_fragments() {
+        let op = &String::from(
+            "
+fragment f on B {
+  c
+  d {
+    e
+  }
+}
+
+{
+  a {
+    ...f
+  }
+}",
+        );
+        let ctx = ApolloCompiler::new(op);
+        let operations = ctx.operations();
+        let operation = operations.first().expect("operation missing");
+        let depth = operation.max_depth(&ctx, &Variables::default()).unwrap();
+        assert_eq!(depth, 3);
+    }
+
+    #[test]
+    fn skip_and_include() {
+        let op = &String::from(
+            "
+query Q($deep: Boolean!) {
+  a {
+    b @include(if: $deep) {
+      c {
+        d
+      }
+    }
+    ... @skip(if: true) {
+      e {
+        f
+      }
+    }
+  }
+}",
+        );
+        let ctx = ApolloCompiler::new(op);
+        let operations = ctx.operations();
+        let operation = operations.first().expect("operation missing");
+
+        let mut provided = Map::new();
+        provided.insert("deep".to_string(), json!(false));
+        let depth = operation
+            .max_depth(&ctx, &Variables::new(operation, &provided))
+            .unwrap();
+        assert_eq!(depth, 1);
+
+        provided.insert("deep".to_string(), json!(true));
+        let depth = operation
+            .max_depth(&ctx, &Variables::new(operation, &provided))
+            .unwrap();
+        assert_eq!(depth, 4);
+    }
+
+    #[test]
+    fn operation_depth() -> anyhow::Result<()> {
+        let op = "query A { a { b } } query B($deep: Boolean = true) { a { b @include(if: $deep) { c } } }";
+
+        assert_eq!(super::operation_depth(op, Some("A"), &Map::new())?.depth, 2);
+        let analysis = super::operation_depth(op, Some("B"), &Map::new())?;
+        assert_eq!(analysis.depth, 3);
+        assert_eq!(analysis.variables, vec!["deep".to_string()]);
+
+        let mut variables = Map::new();
+        variables.insert("deep".to_string(), json!(false));
+        assert_eq!(super::operation_depth(op, Some("B"), &variables)?.depth, 1);
+
+        let err = super::operation_depth(op, Some("C"), &Map::new()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<OperationError>(),
+            Some(&OperationError::MissingOperation)
+        );
+        let err = super::operation_depth("query A { a {", None, &Map::new()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OperationError>(),
+            Some(OperationError::Syntax(_))
+        ));
+
+        let err = super::operation_depth("{ a { ...Nope } }", None, &Map::new()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<OperationError>(),
+            Some(&OperationError::UnknownFragment("Nope".to_string()))
+        );
+        let op = "{ a { ...A } } fragment A on Q { b { ...B } } fragment B on Q { ...A }";
+        let err = super::operation_depth(op, None, &Map::new()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<OperationError>(),
+            Some(&OperationError::FragmentCycle("A".to_string()))
+        );
+        // a fragment can be spread more than once, as long as not in itself
+        let op = "{ a { ...B b { ...B } } } fragment B on Q { c }";
+        assert_eq!(super::operation_depth(op, None, &Map::new())?.depth, 3);
+
+        // each fragment spreads the next twice, 2^29 times in all
+        let mut op = "{ ...F0 }".to_string();
+        for i in 0..29 {
+            op += &format!(
+                " fragment F{} on Q {{ a {{ ...F{} }} ...F{} }}",
+                i,
+                i + 1,
+                i + 1
+            );
+        }
+        op += " fragment F29 on Q { b }";
+        assert_eq!(super::operation_depth(&op, None, &Map::new())?.depth, 30);
+        Ok(())
+    }
+}
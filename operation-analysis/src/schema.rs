@@ -0,0 +1,605 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use anyhow::{anyhow, Result};
+use apollo_compiler::{
+    values::{Directive, FieldDefinition},
+    ApolloCompiler,
+};
+use apollo_parser::{
+    ast::{self, AstNode},
+    Parser, SyntaxNode, SyntaxToken,
+};
+use serde_json::Value as JsonValue;
+
+use crate::compiler_ext::{CompilerAdditions, DirectivesAdditions, TypeAdditions, ValueAdditions};
+
+/// The parts of the supergraph the analyzers need, indexed once when a
+/// plugin is created so requests only have to parse the operation.
+#[derive(Debug, Default)]
+pub struct Schema {
+    types: HashMap<String, TypeInfo>,
+}
+
+#[derive(Debug, Default)]
+pub struct TypeInfo {
+    pub kind: TypeKind,
+    pub fields: HashMap<String, FieldInfo>,
+    /// Interfaces implemented by an object type.
+    pub interfaces: Vec<String>,
+    /// Concrete object types a value of this type can have at runtime.
+    pub possible_types: Vec<String>,
+    /// `@cost(weight:)` on the type.
+    pub weight: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeKind {
+    Object,
+    Interface,
+    Union,
+    Scalar,
+    Enum,
+    InputObject,
+}
+
+impl Default for TypeKind {
+    fn default() -> Self {
+        TypeKind::Object
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FieldInfo {
+    pub type_name: String,
+    pub is_list: bool,
+    pub argument_defaults: HashMap<String, JsonValue>,
+    /// `@cost(weight:)` on the field definition.
+    pub weight: Option<usize>,
+    /// `@listSize(...)` on the field definition.
+    pub list_size: Option<ListSizeDirective>,
+}
+
+#[derive(Debug, Default)]
+pub struct ListSizeDirective {
+    pub assumed_size: Option<usize>,
+    pub slicing_arguments: Option<Vec<String>>,
+    pub sized_fields: Option<Vec<String>>,
+}
+
+impl Schema {
+    pub fn new(sdl: &str) -> Self {
+        let compiler = ApolloCompiler::new(sdl);
+        let mut types = HashMap::new();
+
+        for ty in compiler.object_types().iter() {
+            types.insert(
+                ty.name().to_string(),
+                TypeInfo {
+                    kind: TypeKind::Object,
+                    fields: index_fields(ty.fields_definition()),
+                    interfaces: compiler.implemented_interfaces(ty.name()),
+                    possible_types: compiler.possible_types(ty.name()),
+                    weight: type_weight(&compiler, ty.name()),
+                },
+            );
+        }
+
+        for ty in compiler.interfaces().iter() {
+            types.insert(
+                ty.name().to_string(),
+                TypeInfo {
+                    kind: TypeKind::Interface,
+                    fields: index_fields(ty.fields_definition()),
+                    possible_types: compiler.possible_types(ty.name()),
+                    ..Default::default()
+                },
+            );
+        }
+
+        for ty in compiler.unions().iter() {
+            types.insert(
+                ty.name().to_string(),
+                TypeInfo {
+                    kind: TypeKind::Union,
+                    possible_types: compiler.possible_types(ty.name()),
+                    ..Default::default()
+                },
+            );
+        }
+
+        for (name, kind) in compiler
+            .scalars()
+            .iter()
+            .map(|ty| (ty.name(), TypeKind::Scalar))
+            .chain(
+                compiler
+                    .enums()
+                    .iter()
+                    .map(|ty| (ty.name(), TypeKind::Enum)),
+            )
+        {
+            types.insert(
+                name.to_string(),
+                TypeInfo {
+                    kind,
+                    weight: type_weight(&compiler, name),
+                    ..Default::default()
+                },
+            );
+        }
+
+        for ty in compiler.input_objects().iter() {
+            types.insert(
+                ty.name().to_string(),
+                TypeInfo {
+                    kind: TypeKind::InputObject,
+                    ..Default::default()
+                },
+            );
+        }
+
+        Self { types }
+    }
+
+    /// Merges the `@cost` and `@listSize` directives of an overlay, an SDL
+    /// document extending the schema's types (e.g. `extend type Product
+    /// @cost(weight: 5)`), over the schema's own. Directives that can't be
+    /// applied (on unknown types or fields, unions or input types) are
+    /// skipped and listed in the error.
+    pub fn apply_overlay(&mut self, sdl: &str) -> Result<()> {
+        let ast = Parser::new(sdl).parse();
+        let errors: Vec<_> = ast.errors().map(|err| err.message().to_string()).collect();
+        if !errors.is_empty() {
+            return Err(anyhow!("syntax errors: {}", errors.join(", ")));
+        }
+
+        // the compiler only indexes definitions, so extensions are handed to
+        // it without their `extend` keyword
+        let mut sdl = Vec::new();
+        let mut invalid = Vec::new();
+        for definition in ast.document().definitions() {
+            match definition {
+                ast::Definition::ObjectTypeExtension(ext) => {
+                    sdl.push(without_extend(ext.syntax(), ext.extend_token()))
+                }
+                ast::Definition::InterfaceTypeExtension(ext) => {
+                    sdl.push(without_extend(ext.syntax(), ext.extend_token()))
+                }
+                ast::Definition::ScalarTypeExtension(ext) => {
+                    sdl.push(without_extend(ext.syntax(), ext.extend_token()))
+                }
+                ast::Definition::EnumTypeExtension(ext) => {
+                    sdl.push(without_extend(ext.syntax(), ext.extend_token()))
+                }
+                ast::Definition::ObjectTypeDefinition(_)
+                | ast::Definition::InterfaceTypeDefinition(_)
+                | ast::Definition::ScalarTypeDefinition(_)
+                | ast::Definition::EnumTypeDefinition(_) => {
+                    sdl.push(definition.syntax().to_string())
+                }
+                ast::Definition::UnionTypeExtension(ext) if has_cost_directives(ext.syntax()) => {
+                    invalid.push(format!("{} (unions have no cost)", name_of(ext.name())))
+                }
+                ast::Definition::UnionTypeDefinition(def) if has_cost_directives(def.syntax()) => {
+                    invalid.push(format!("{} (unions have no cost)", name_of(def.name())))
+                }
+                ast::Definition::InputObjectTypeExtension(ext)
+                    if has_cost_directives(ext.syntax()) =>
+                {
+                    invalid.push(format!(
+                        "{} ({})",
+                        name_of(ext.name()),
+                        InvalidCoordinate::InputField
+                    ))
+                }
+                ast::Definition::InputObjectTypeDefinition(def)
+                    if has_cost_directives(def.syntax()) =>
+                {
+                    invalid.push(format!(
+                        "{} ({})",
+                        name_of(def.name()),
+                        InvalidCoordinate::InputField
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        let compiler = ApolloCompiler::new(&sdl.join("\n"));
+        let definitions = compiler
+            .object_types()
+            .iter()
+            .map(|ty| {
+                (
+                    ty.name().to_string(),
+                    ty.directives().to_vec(),
+                    ty.fields_definition().to_vec(),
+                )
+            })
+            .chain(compiler.interfaces().iter().map(|ty| {
+                (
+                    ty.name().to_string(),
+                    ty.directives().to_vec(),
+                    ty.fields_definition().to_vec(),
+                )
+            }))
+            .chain(
+                compiler
+                    .scalars()
+                    .iter()
+                    .map(|ty| (ty.name().to_string(), ty.directives().to_vec(), Vec::new())),
+            )
+            .chain(
+                compiler
+                    .enums()
+                    .iter()
+                    .map(|ty| (ty.name().to_string(), ty.directives().to_vec(), Vec::new())),
+            );
+
+        for (type_name, directives, fields) in definitions {
+            let ty = match self.types.get_mut(&type_name) {
+                Some(ty) if ty.kind == TypeKind::InputObject => {
+                    invalid.push(format!("{} ({})", type_name, InvalidCoordinate::InputField));
+                    continue;
+                }
+                Some(ty) => ty,
+                None => {
+                    invalid.push(format!(
+                        "{} ({})",
+                        type_name,
+                        InvalidCoordinate::UnknownType
+                    ));
+                    continue;
+                }
+            };
+
+            if let Some(weight) = cost_weight(&directives) {
+                ty.weight = Some(weight);
+            }
+            for field in fields {
+                match ty.fields.get_mut(field.name()) {
+                    Some(info) => {
+                        if let Some(weight) = cost_weight(field.directives()) {
+                            info.weight = Some(weight);
+                        }
+                        if let Some(list_size) = list_size_directive(field.directives()) {
+                            info.list_size = Some(list_size);
+                        }
+                    }
+                    None => invalid.push(format!(
+                        "{}.{} ({})",
+                        type_name,
+                        field.name(),
+                        InvalidCoordinate::UnknownField
+                    )),
+                }
+            }
+        }
+
+        invalid.sort();
+        invalid.dedup();
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("invalid overlay entries: {}", invalid.join(", ")))
+        }
+    }
+
+    pub fn type_info(&self, type_name: &str) -> Option<&TypeInfo> {
+        self.types.get(type_name)
+    }
+
+    pub fn field(&self, type_name: &str, field_name: &str) -> Option<&FieldInfo> {
+        self.types
+            .get(type_name)
+            .and_then(|ty| ty.fields.get(field_name))
+    }
+
+    pub fn implemented_interfaces(&self, type_name: &str) -> &[String] {
+        self.types
+            .get(type_name)
+            .map(|ty| ty.interfaces.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn possible_types(&self, type_name: &str) -> &[String] {
+        self.types
+            .get(type_name)
+            .map(|ty| ty.possible_types.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Checks that `coordinate` names a field of an object or interface
+    /// type, i.e. something a `cost_map` entry can weight.
+    pub fn check_coordinate(&self, coordinate: &str) -> Result<(), InvalidCoordinate> {
+        let (type_name, field_name) = coordinate
+            .split_once('.')
+            .filter(|(type_name, field_name)| is_name(type_name) && is_name(field_name))
+            .ok_or(InvalidCoordinate::Malformed)?;
+
+        let ty = self
+            .types
+            .get(type_name)
+            .ok_or(InvalidCoordinate::UnknownType)?;
+        match ty.kind {
+            TypeKind::InputObject => Err(InvalidCoordinate::InputField),
+            TypeKind::Enum => Err(InvalidCoordinate::EnumValue),
+            _ if ty.fields.contains_key(field_name) => Ok(()),
+            _ => Err(InvalidCoordinate::UnknownField),
+        }
+    }
+
+    pub fn type_condition_applies(&self, type_condition: &str, concrete_name: &str) -> bool {
+        type_condition == concrete_name
+            || self
+                .possible_types(type_condition)
+                .iter()
+                .any(|ty| ty == concrete_name)
+    }
+}
+
+/// Why a coordinate doesn't name a field the cost can be weighted for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidCoordinate {
+    /// Not of the form `Type.field`.
+    Malformed,
+    UnknownType,
+    UnknownField,
+    /// A field of an input object type.
+    InputField,
+    /// A value of an enum type.
+    EnumValue,
+}
+
+impl Display for InvalidCoordinate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InvalidCoordinate::Malformed => "expected Type.field",
+            InvalidCoordinate::UnknownType => "unknown type",
+            InvalidCoordinate::UnknownField => "unknown field",
+            InvalidCoordinate::InputField => "input fields have no cost",
+            InvalidCoordinate::EnumValue => "enum values have no cost",
+        })
+    }
+}
+
+/// The source of a type extension, from after its `extend` keyword.
+fn without_extend(node: &SyntaxNode, extend: Option<SyntaxToken>) -> String {
+    let text = node.to_string();
+    match extend {
+        Some(extend) => {
+            text[usize::from(extend.text_range().end() - node.text_range().start())..].to_string()
+        }
+        None => text,
+    }
+}
+
+/// Whether a definition or extension has `@cost` or `@listSize` directives,
+/// on itself or its members.
+fn has_cost_directives(node: &SyntaxNode) -> bool {
+    node.descendants()
+        .filter_map(ast::Directive::cast)
+        .filter_map(|directive| directive.name())
+        .any(|name| matches!(name.text().to_string().as_str(), "cost" | "listSize"))
+}
+
+fn name_of(name: Option<ast::Name>) -> String {
+    name.map(|name| name.text().to_string()).unwrap_or_default()
+}
+
+/// Whether `name` is a valid GraphQL name.
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn index_fields(fields: &[FieldDefinition]) -> HashMap<String, FieldInfo> {
+    fields
+        .iter()
+        .map(|field| {
+            let info = FieldInfo {
+                type_name: field.ty().name(),
+                is_list: field.ty().is_list(),
+                argument_defaults: field
+                    .arguments()
+                    .input_values()
+                    .iter()
+                    .filter_map(|arg| {
+                        arg.default_value()
+                            .and_then(|value| value.to_json())
+                            .map(|value| (arg.name().to_string(), value))
+                    })
+                    .collect(),
+                weight: cost_weight(field.directives()),
+                list_size: list_size_directive(field.directives()),
+            };
+
+            (field.name().to_string(), info)
+        })
+        .collect()
+}
+
+fn type_weight(compiler: &ApolloCompiler, type_name: &str) -> Option<usize> {
+    cost_weight(&compiler.type_directives(type_name))
+}
+
+/// `@cost(weight:)` among `directives`.
+fn cost_weight(directives: &[Directive]) -> Option<usize> {
+    directives
+        .argument("cost", "weight")
+        .and_then(|value| value.as_usize())
+}
+
+/// `@listSize(...)` among `directives`.
+fn list_size_directive(directives: &[Directive]) -> Option<ListSizeDirective> {
+    directives
+        .iter()
+        .any(|directive| directive.name() == "listSize")
+        .then(|| ListSizeDirective {
+            assumed_size: directives
+                .argument("listSize", "assumedSize")
+                .and_then(|value| value.as_usize()),
+            slicing_arguments: directives
+                .argument("listSize", "slicingArguments")
+                .and_then(|value| value.as_str_list()),
+            sized_fields: directives
+                .argument("listSize", "sizedFields")
+                .and_then(|value| value.as_str_list()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InvalidCoordinate, Schema};
+
+    #[test]
+    fn index() {
+        let schema = Schema::new(
+            "type Query { a(first: Int = 5): [A] @cost(weight: 2) @listSize(assumedSize: 3) u: U }
+             interface A { b: String }
+             type A1 implements A @cost(weight: 4) { b: String }
+             type A2 implements A { b: String }
+             union U = A1 | A2",
+        );
+
+        let field = schema.field("Query", "a").expect("field missing");
+        assert_eq!(field.type_name, "A");
+        assert!(field.is_list);
+        assert_eq!(field.weight, Some(2));
+        assert_eq!(field.argument_defaults["first"], 5);
+        assert_eq!(
+            field.list_size.as_ref().and_then(|l| l.assumed_size),
+            Some(3)
+        );
+
+        assert_eq!(schema.possible_types("A"), ["A1", "A2"]);
+        assert_eq!(schema.possible_types("U"), ["A1", "A2"]);
+        assert_eq!(schema.implemented_interfaces("A1"), ["A"]);
+        assert_eq!(schema.type_info("A1").and_then(|ty| ty.weight), Some(4));
+        assert!(schema.type_condition_applies("U", "A2"));
+        assert!(!schema.type_condition_applies("A1", "A2"));
+    }
+
+    #[test]
+    fn check_coordinate() {
+        let schema = Schema::new(
+            "type Query { a(input: I): A }
+             interface A { b: String }
+             type A1 implements A { b: String c: E }
+             enum E { X Y }
+             input I { d: String }
+             scalar S",
+        );
+
+        assert_eq!(schema.check_coordinate("Query.a"), Ok(()));
+        assert_eq!(schema.check_coordinate("A.b"), Ok(()));
+        assert_eq!(schema.check_coordinate("A1.c"), Ok(()));
+        assert_eq!(
+            schema.check_coordinate("Query.b"),
+            Err(InvalidCoordinate::UnknownField)
+        );
+        assert_eq!(
+            schema.check_coordinate("S.a"),
+            Err(InvalidCoordinate::UnknownField)
+        );
+        assert_eq!(
+            schema.check_coordinate("B.a"),
+            Err(InvalidCoordinate::UnknownType)
+        );
+        assert_eq!(
+            schema.check_coordinate("I.d"),
+            Err(InvalidCoordinate::InputField)
+        );
+        assert_eq!(
+            schema.check_coordinate("E.X"),
+            Err(InvalidCoordinate::EnumValue)
+        );
+        for coordinate in [
+            "Query",
+            "Query.",
+            ".a",
+            "Query.a.b",
+            "Query.a(input:)",
+            "1Query.a",
+        ] {
+            assert_eq!(
+                schema.check_coordinate(coordinate),
+                Err(InvalidCoordinate::Malformed),
+                "{}",
+                coordinate
+            );
+        }
+    }
+
+    #[test]
+    fn overlay() {
+        let mut schema = Schema::new(
+            "type Query { a(first: Int): [A] @cost(weight: 2) b: B }
+             type A { c: String }
+             scalar B",
+        );
+
+        schema
+            .apply_overlay(
+                "# weights owned by the schema team
+                 extend type Query {
+                   a: [A] @cost(weight: 3) @listSize(slicingArguments: [\"first\"], assumedSize: 20)
+                 }
+                 extend scalar B @cost(weight: 4)",
+            )
+            .unwrap();
+        let field = schema.field("Query", "a").expect("field missing");
+        assert_eq!(field.weight, Some(3));
+        assert_eq!(
+            field.list_size.as_ref().and_then(|l| l.assumed_size),
+            Some(20)
+        );
+        assert_eq!(schema.type_info("B").and_then(|ty| ty.weight), Some(4));
+
+        let err = schema
+            .apply_overlay(
+                "extend type A @cost(weight: 5) { c: String @cost(weight: 6) d: String @cost(weight: 1) }
+                 extend type C @cost(weight: 1)",
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid overlay entries: A.d (unknown field), C (unknown type)"
+        );
+        // the rest of the overlay still applies
+        assert_eq!(schema.type_info("A").and_then(|ty| ty.weight), Some(5));
+        assert_eq!(
+            schema.field("A", "c").and_then(|field| field.weight),
+            Some(6)
+        );
+
+        // extensions are read from the syntax tree, not line by line
+        schema
+            .apply_overlay(
+                "extend
+                 type A @cost(weight: 7) extend scalar B @cost(weight: 8)
+                 \"\"\"
+                 extend type C @cost(weight: 1)
+                 \"\"\"
+                 directive @cost(weight: Int!) on OBJECT | SCALAR",
+            )
+            .unwrap();
+        assert_eq!(schema.type_info("A").and_then(|ty| ty.weight), Some(7));
+        assert_eq!(schema.type_info("B").and_then(|ty| ty.weight), Some(8));
+
+        let err = schema
+            .apply_overlay(
+                "extend union U @cost(weight: 1)
+                 extend input I { i: Int @cost(weight: 1) }
+                 extend union V = A",
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid overlay entries: I (input fields have no cost), U (unions have no cost)"
+        );
+        assert!(schema.apply_overlay("extend type A {").is_err());
+    }
+}
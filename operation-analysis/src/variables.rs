@@ -0,0 +1,180 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+
+use apollo_compiler::values::{Directive, OperationDefinition, Value};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::compiler_ext::ValueAdditions;
+
+/// Variable values for a single operation: the request's variables, falling
+/// back to the defaults declared in the operation's variable definitions.
+#[derive(Debug, Default)]
+pub struct Variables {
+    values: HashMap<String, JsonValue>,
+    /// Names of the variables resolved so far.
+    used: RefCell<BTreeSet<String>>,
+}
+
+impl Variables {
+    pub fn new(operation: &OperationDefinition, provided: &Map<String, JsonValue>) -> Self {
+        let values = operation
+            .variables()
+            .iter()
+            .filter_map(|definition| {
+                let name = definition.name();
+                provided
+                    .get(name)
+                    .cloned()
+                    .or_else(|| definition.default_value().and_then(|value| value.to_json()))
+                    .map(|value| (name.to_string(), value))
+            })
+            .collect();
+
+        Self {
+            values,
+            used: Default::default(),
+        }
+    }
+
+    /// Resolves an argument value, substituting variables, including those
+    /// nested in lists and objects. As when executing the operation, nested
+    /// variables without a value are null in lists and leave their field out
+    /// of objects.
+    pub fn resolve(&self, value: &Value) -> Option<JsonValue> {
+        match value {
+            Value::Variable(variable) => {
+                self.used.borrow_mut().insert(variable.name().to_string());
+                self.values.get(variable.name()).cloned()
+            }
+            Value::List(values) => Some(JsonValue::Array(
+                values
+                    .iter()
+                    .map(|value| self.resolve(value).unwrap_or(JsonValue::Null))
+                    .collect(),
+            )),
+            Value::Object(fields) => Some(JsonValue::Object(
+                fields
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        self.resolve(value).map(|value| (name.to_string(), value))
+                    })
+                    .collect(),
+            )),
+            value => value.to_json(),
+        }
+    }
+
+    /// Values of every variable. Marks them all as used, since the caller
+    /// may read any of them.
+    pub fn all(&self) -> &HashMap<String, JsonValue> {
+        self.used.borrow_mut().extend(self.values.keys().cloned());
+        &self.values
+    }
+
+    /// Names of the variables that have been resolved, i.e. the ones whose
+    /// values could have affected an analysis using these variables.
+    pub fn used(&self) -> Vec<String> {
+        self.used.borrow().iter().cloned().collect()
+    }
+
+    /// Whether `@skip` and `@include` let a selection with these directives
+    /// execute. Conditions that can't be resolved count as included.
+    pub fn is_included(&self, directives: &[Directive]) -> bool {
+        directives.iter().all(|directive| {
+            let condition = directive
+                .arguments()
+                .iter()
+                .find(|arg| arg.name() == "if")
+                .and_then(|arg| self.resolve(arg.value()))
+                .and_then(|value| value.as_bool());
+
+            !matches!(
+                (directive.name(), condition),
+                ("skip", Some(true)) | ("include", Some(false))
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apollo_compiler::{values::Selection, ApolloCompiler};
+    use serde_json::{json, Map};
+
+    use crate::compiler_ext::CompilerAdditions;
+
+    use super::Variables;
+
+    #[test]
+    fn provided_and_default_values() {
+        let ctx = ApolloCompiler::new(
+            &"query Q($a: Int, $b: Int = 3, $c: Boolean) { a(x: $a, y: $b, z: $c) }".to_string(),
+        );
+        let operation = ctx.operation_by_name(Some("Q")).expect("operation missing");
+
+        let mut provided = Map::new();
+        provided.insert("a".to_string(), json!(7));
+        let variables = Variables::new(&operation, &provided);
+
+        let field = match operation.selection_set().selection().first() {
+            Some(Selection::Field(f)) => f.clone(),
+            _ => panic!("field missing"),
+        };
+        let resolved: Vec<_> = field
+            .arguments()
+            .iter()
+            .map(|arg| variables.resolve(arg.value()))
+            .collect();
+
+        assert_eq!(resolved, vec![Some(json!(7)), Some(json!(3)), None]);
+        assert_eq!(variables.used(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn nested_variables() {
+        let ctx = ApolloCompiler::new(
+            &"query Q($n: String, $tag: String = \"new\", $missing: String) { search(filter: { name: $n, tags: [$tag, $missing, \"sale\"], other: $missing }) }".to_string(),
+        );
+        let operation = ctx.operation_by_name(Some("Q")).expect("operation missing");
+
+        let mut provided = Map::new();
+        provided.insert("n".to_string(), json!("shoe"));
+        let variables = Variables::new(&operation, &provided);
+
+        let field = match operation.selection_set().selection().first() {
+            Some(Selection::Field(f)) => f.clone(),
+            _ => panic!("field missing"),
+        };
+        let resolved = variables.resolve(field.arguments()[0].value());
+
+        assert_eq!(
+            resolved,
+            Some(json!({ "name": "shoe", "tags": ["new", null, "sale"] }))
+        );
+        assert_eq!(variables.used(), vec!["missing", "n", "tag"]);
+    }
+
+    #[test]
+    fn skip_and_include() {
+        let ctx = ApolloCompiler::new(
+            &"query Q($yes: Boolean = true, $no: Boolean!, $unknown: Boolean) { a @skip(if: true) b @include(if: $yes) c @include(if: $no) d @skip(if: $unknown) e @include(if: true) @skip(if: $yes) }".to_string(),
+        );
+        let operation = ctx.operation_by_name(Some("Q")).expect("operation missing");
+
+        let mut provided = Map::new();
+        provided.insert("no".to_string(), json!(false));
+        let variables = Variables::new(&operation, &provided);
+
+        let included: Vec<_> = operation
+            .selection_set()
+            .selection()
+            .iter()
+            .map(|selection| match selection {
+                Selection::Field(f) => variables.is_included(f.directives()),
+                _ => panic!("field expected"),
+            })
+            .collect();
+
+        assert_eq!(included, vec![false, true, false, true, false]);
+    }
+}
@@ -0,0 +1,37 @@
+-- Refills and debits a token bucket atomically.
+--
+-- KEYS[1]: bucket key
+-- ARGV[1]: capacity
+-- ARGV[2]: refill per second
+-- ARGV[3]: cost
+--
+-- Returns {allowed (0 or 1), tokens left}. Tokens are returned as a string
+-- because Redis truncates Lua numbers to integers.
+local capacity = tonumber(ARGV[1])
+local refill_per_second = tonumber(ARGV[2])
+local cost = tonumber(ARGV[3])
+
+-- required before writing after a non-deterministic command on Redis < 5
+redis.replicate_commands()
+local time = redis.call('TIME')
+local now = tonumber(time[1]) + tonumber(time[2]) / 1000000
+
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or capacity
+local updated = tonumber(bucket[2]) or now
+
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * refill_per_second)
+
+local allowed = 0
+if cost <= tokens then
+  tokens = tokens - cost
+  allowed = 1
+end
+
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', tostring(now))
+if refill_per_second > 0 then
+  -- a full bucket is the same as no bucket
+  redis.call('EXPIRE', KEYS[1], math.ceil(capacity / refill_per_second) + 1)
+end
+
+return {allowed, tostring(tokens)}
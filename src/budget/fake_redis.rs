@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use mlua::{Lua, Value as LuaValue, Variadic};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// In-process stand-in for Redis, for tests. It speaks enough of the Redis
+/// protocol for [`RedisStore`](super::RedisStore), and runs scripts with
+/// Lua 5.1 as Redis does, one at a time, on a clock the test controls.
+#[derive(Clone, Debug)]
+pub(crate) struct FakeRedis {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Seconds since the epoch, as returned by `TIME`.
+    now: f64,
+    hashes: HashMap<String, Hash>,
+    /// Loaded scripts, by SHA-1.
+    scripts: HashMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+struct Hash {
+    fields: HashMap<String, String>,
+    expires_at: Option<f64>,
+}
+
+#[derive(Debug)]
+enum Reply {
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+    Error(String),
+}
+
+impl FakeRedis {
+    pub(crate) async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State {
+            now: 1_600_000_000.0,
+            ..Default::default()
+        }));
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, server_state.clone()));
+            }
+        });
+
+        Self { url, state }
+    }
+
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub(crate) fn advance(&self, secs: f64) {
+        self.state.lock().unwrap().now += secs;
+    }
+
+    /// Seconds until `key` expires, if it exists and has an expiry.
+    pub(crate) fn ttl(&self, key: &str) -> Option<f64> {
+        let state = self.state.lock().unwrap();
+        state
+            .hashes
+            .get(key)
+            .and_then(|hash| hash.expires_at)
+            .map(|expires_at| expires_at - state.now)
+            .filter(|ttl| *ttl > 0.0)
+    }
+}
+
+async fn serve(stream: TcpStream, state: Arc<Mutex<State>>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    while let Some(command) = read_command(&mut reader).await {
+        let reply = execute(&mut state.lock().unwrap(), &command);
+        let mut out = Vec::new();
+        encode(&reply, &mut out);
+        if writer.write_all(&out).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Reads a command, sent as an array of bulk strings.
+async fn read_command<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<Vec<String>> {
+    let count = read_header(reader, '*').await?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len = read_header(reader, '$').await?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).await.ok()?;
+        arg.truncate(len);
+        args.push(String::from_utf8(arg).ok()?);
+    }
+    Some(args)
+}
+
+/// Reads a `<prefix><length>\r\n` line.
+async fn read_header<R: AsyncBufReadExt + Unpin>(reader: &mut R, prefix: char) -> Option<usize> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    line.strip_prefix(prefix)?.trim_end().parse().ok()
+}
+
+fn encode(reply: &Reply, out: &mut Vec<u8>) {
+    match reply {
+        Reply::Integer(i) => out.extend_from_slice(format!(":{}\r\n", i).as_bytes()),
+        Reply::Bulk(Some(s)) => {
+            out.extend_from_slice(format!("${}\r\n{}\r\n", s.len(), s).as_bytes())
+        }
+        Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+        Reply::Array(items) => {
+            out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+            for item in items {
+                encode(item, out);
+            }
+        }
+        Reply::Error(message) => out.extend_from_slice(format!("-{}\r\n", message).as_bytes()),
+    }
+}
+
+/// Runs a command sent by a client.
+fn execute(state: &mut State, args: &[String]) -> Reply {
+    let (name, args) = match args.split_first() {
+        Some((name, args)) => (name.to_ascii_uppercase(), args),
+        None => return Reply::Error("ERR empty command".to_string()),
+    };
+
+    match (name.as_str(), args) {
+        ("SCRIPT", [subcommand, code]) if subcommand.eq_ignore_ascii_case("load") => {
+            let sha = format!("{:x}", Sha1::digest(code.as_bytes()));
+            state.scripts.insert(sha.clone(), code.clone());
+            Reply::Bulk(Some(sha))
+        }
+        ("EVALSHA", [sha, args @ ..]) => match state.scripts.get(sha).cloned() {
+            Some(code) => eval(state, &code, args),
+            None => Reply::Error("NOSCRIPT No matching script.".to_string()),
+        },
+        ("EVAL", [code, args @ ..]) => eval(state, code, args),
+        _ => call(state, &name, args),
+    }
+}
+
+/// Runs a command sent by a client or a script, on the data.
+fn call(state: &mut State, name: &str, args: &[String]) -> Reply {
+    let now = state.now;
+    state
+        .hashes
+        .retain(|_, hash| hash.expires_at.map_or(true, |expires_at| expires_at > now));
+
+    match (name.to_ascii_uppercase().as_str(), args) {
+        ("TIME", []) => Reply::Array(vec![
+            Reply::Bulk(Some((now.trunc() as u64).to_string())),
+            Reply::Bulk(Some(
+                ((now.fract() * 1_000_000.0).round() as u64).to_string(),
+            )),
+        ]),
+        ("HMGET", [key, fields @ ..]) => {
+            let hash = state.hashes.get(key);
+            Reply::Array(
+                fields
+                    .iter()
+                    .map(|field| Reply::Bulk(hash.and_then(|hash| hash.fields.get(field).cloned())))
+                    .collect(),
+            )
+        }
+        ("HSET", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            let hash = state.hashes.entry(key.clone()).or_default();
+            let mut added = 0;
+            for pair in pairs.chunks(2) {
+                if hash
+                    .fields
+                    .insert(pair[0].clone(), pair[1].clone())
+                    .is_none()
+                {
+                    added += 1;
+                }
+            }
+            Reply::Integer(added)
+        }
+        ("EXPIRE", [key, secs]) => match (secs.parse::<i64>(), state.hashes.get_mut(key)) {
+            (Ok(secs), Some(hash)) => {
+                hash.expires_at = Some(now + secs as f64);
+                Reply::Integer(1)
+            }
+            (Ok(_), None) => Reply::Integer(0),
+            (Err(_), _) => Reply::Error("ERR value is not an integer".to_string()),
+        },
+        _ => Reply::Error(format!("ERR unknown command '{}'", name)),
+    }
+}
+
+fn eval(state: &mut State, code: &str, args: &[String]) -> Reply {
+    let (keys, argv) = match args.split_first() {
+        Some((count, rest)) => match count.parse::<usize>() {
+            Ok(count) if count <= rest.len() => rest.split_at(count),
+            _ => return Reply::Error("ERR invalid number of keys".to_string()),
+        },
+        None => return Reply::Error("ERR wrong number of arguments".to_string()),
+    };
+
+    run_script(state, code, keys, argv).unwrap_or_else(|err| {
+        // errors are sent on one line
+        let message = err.to_string().replace(['\r', '\n'], " ");
+        Reply::Error(format!("ERR Error running script: {}", message))
+    })
+}
+
+fn run_script(
+    state: &mut State,
+    code: &str,
+    keys: &[String],
+    argv: &[String],
+) -> mlua::Result<Reply> {
+    let lua = Lua::new();
+    lua.globals().set("KEYS", keys.to_vec())?;
+    lua.globals().set("ARGV", argv.to_vec())?;
+
+    lua.scope(|scope| {
+        let redis = lua.create_table()?;
+        redis.set(
+            "call",
+            scope.create_function_mut(|lua, args: Variadic<LuaValue>| {
+                let args = args
+                    .into_iter()
+                    .map(lua_to_arg)
+                    .collect::<mlua::Result<Vec<_>>>()?;
+                let (name, args) = args
+                    .split_first()
+                    .ok_or_else(|| mlua::Error::RuntimeError("missing command".to_string()))?;
+                reply_to_lua(lua, call(state, name, args))
+            })?,
+        )?;
+        redis.set("replicate_commands", lua.create_function(|_, ()| Ok(true))?)?;
+        lua.globals().set("redis", redis)?;
+
+        let result: LuaValue = lua.load(code).call(())?;
+        lua_to_reply(result)
+    })
+}
+
+/// Converts an argument of `redis.call`, which Redis sends as a string.
+fn lua_to_arg(value: LuaValue) -> mlua::Result<String> {
+    match value {
+        LuaValue::String(s) => Ok(s.to_str()?.to_string()),
+        LuaValue::Integer(i) => Ok(i.to_string()),
+        LuaValue::Number(n) if n.fract() == 0.0 => Ok((n as i64).to_string()),
+        LuaValue::Number(n) => Ok(n.to_string()),
+        _ => Err(mlua::Error::RuntimeError(
+            "arguments must be strings or numbers".to_string(),
+        )),
+    }
+}
+
+/// Converts the result of `redis.call` as Redis does, e.g. nil bulk strings
+/// to `false`.
+fn reply_to_lua(lua: &Lua, reply: Reply) -> mlua::Result<LuaValue> {
+    Ok(match reply {
+        Reply::Integer(i) => LuaValue::Number(i as f64),
+        Reply::Bulk(Some(s)) => LuaValue::String(lua.create_string(&s)?),
+        Reply::Bulk(None) => LuaValue::Boolean(false),
+        Reply::Array(items) => {
+            let table = lua.create_table()?;
+            for (i, item) in items.into_iter().enumerate() {
+                table.set(i + 1, reply_to_lua(lua, item)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Reply::Error(message) => return Err(mlua::Error::RuntimeError(message)),
+    })
+}
+
+/// Converts a script's result as Redis does, e.g. numbers to integers,
+/// truncating them.
+fn lua_to_reply(value: LuaValue) -> mlua::Result<Reply> {
+    Ok(match value {
+        LuaValue::Integer(i) => Reply::Integer(i),
+        LuaValue::Number(n) => Reply::Integer(n as i64),
+        LuaValue::String(s) => Reply::Bulk(Some(s.to_str()?.to_string())),
+        LuaValue::Boolean(true) => Reply::Integer(1),
+        LuaValue::Table(table) => Reply::Array(
+            table
+                .sequence_values::<LuaValue>()
+                .map(|value| value.and_then(lua_to_reply))
+                .collect::<mlua::Result<_>>()?,
+        ),
+        _ => Reply::Bulk(None),
+    })
+}
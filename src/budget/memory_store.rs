@@ -0,0 +1,124 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+use lru::LruCache;
+use tower::BoxError;
+
+use super::{BucketLimits, BudgetStore, Debit};
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets kept in memory, so each router instance has its own.
+pub struct MemoryStore {
+    limits: BucketLimits,
+    buckets: Mutex<LruCache<String, Bucket>>,
+}
+
+impl MemoryStore {
+    pub fn new(limits: BucketLimits, max_clients: usize) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(LruCache::new(max_clients)),
+        }
+    }
+
+    fn debit_at(&self, client: &str, cost: usize, now: Instant) -> Debit {
+        let capacity = self.limits.capacity as f64;
+        let mut buckets = self.buckets.lock().expect("budget lock poisoned");
+        if !buckets.contains(client) {
+            buckets.put(
+                client.to_string(),
+                Bucket {
+                    tokens: capacity,
+                    updated: now,
+                },
+            );
+        }
+        let bucket = buckets.get_mut(client).expect("bucket exists");
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        let tokens = (bucket.tokens + elapsed * self.limits.refill_per_second).min(capacity);
+        let (tokens, debit) = self.limits.debit(tokens, cost);
+        bucket.tokens = tokens;
+        bucket.updated = now;
+
+        debit
+    }
+}
+
+#[async_trait::async_trait]
+impl BudgetStore for MemoryStore {
+    async fn debit(&self, client: &str, cost: usize) -> Result<Debit, BoxError> {
+        Ok(self.debit_at(client, cost, Instant::now()))
+    }
+}
+
+impl std::fmt::Debug for MemoryStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryStore")
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::MemoryStore;
+    use crate::budget::tests::{check_store, TEST_LIMITS};
+    use crate::budget::{BucketLimits, Debit};
+
+    fn store() -> MemoryStore {
+        MemoryStore::new(
+            BucketLimits {
+                capacity: 100,
+                refill_per_second: 10.0,
+            },
+            10,
+        )
+    }
+
+    #[test]
+    fn debit_and_refill() {
+        let store = store();
+        let start = Instant::now();
+
+        assert_eq!(
+            store.debit_at("a", 80, start),
+            Debit::Allowed { remaining: 20 }
+        );
+        assert_eq!(
+            store.debit_at("a", 50, start),
+            Debit::Denied {
+                remaining: 20,
+                retry_after: Duration::from_secs(3)
+            }
+        );
+        // other clients have their own budget
+        assert_eq!(
+            store.debit_at("b", 50, start),
+            Debit::Allowed { remaining: 50 }
+        );
+
+        assert_eq!(
+            store.debit_at("a", 50, start + Duration::from_secs(3)),
+            Debit::Allowed { remaining: 0 }
+        );
+        // refills up to the capacity only
+        assert_eq!(
+            store.debit_at("a", 0, start + Duration::from_secs(60)),
+            Debit::Allowed { remaining: 100 }
+        );
+    }
+
+    #[tokio::test]
+    async fn contract() {
+        let store = MemoryStore::new(TEST_LIMITS, 10);
+        check_store(&store, &store).await;
+    }
+}
@@ -0,0 +1,450 @@
+#[cfg(test)]
+mod fake_redis;
+mod memory_store;
+mod redis_store;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use http::HeaderMap;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+
+pub use memory_store::MemoryStore;
+pub use redis_store::RedisStore;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct BudgetConf {
+    #[serde(flatten)]
+    pub limits: BucketLimits,
+    /// How requests are attributed to clients.
+    pub client_id: ClientId,
+    /// Where budgets are kept. Defaults to memory.
+    #[serde(default)]
+    pub store: StoreConf,
+    /// What to do with requests when the store can't be reached.
+    #[serde(default)]
+    pub on_store_error: OnStoreError,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+pub struct BucketLimits {
+    /// Maximum cost a client can spend in a burst.
+    pub capacity: usize,
+    /// Cost added back to each client's budget per second.
+    pub refill_per_second: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreConf {
+    /// Budgets kept in memory, per router instance.
+    Memory {
+        /// Number of client budgets kept. Evicted clients start over with a
+        /// full budget.
+        #[serde(default = "default_max_clients")]
+        max_clients: usize,
+    },
+    /// Budgets kept in Redis, shared by every router using the same keys.
+    Redis {
+        /// e.g. `redis://localhost:6379`
+        url: String,
+        #[serde(default = "default_key_prefix")]
+        key_prefix: String,
+        /// Time allowed for each debit before it counts as a store error.
+        #[serde(default = "default_timeout_ms")]
+        timeout_ms: u64,
+    },
+}
+
+impl Default for StoreConf {
+    fn default() -> Self {
+        StoreConf::Memory {
+            max_clients: default_max_clients(),
+        }
+    }
+}
+
+fn default_max_clients() -> usize {
+    10_000
+}
+
+fn default_key_prefix() -> String {
+    "cost_budget:".to_string()
+}
+
+fn default_timeout_ms() -> u64 {
+    100
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnStoreError {
+    /// Let the request through (fail open).
+    Allow,
+    /// Reject the request (fail closed).
+    Reject,
+}
+
+impl Default for OnStoreError {
+    fn default() -> Self {
+        OnStoreError::Allow
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientId {
+    /// Value of the named request header.
+    Header(String),
+    /// Named claim of the bearer token in the `authorization` header. The
+    /// token's signature is not verified, so this must only be used behind
+    /// something that does.
+    JwtClaim(String),
+    /// Address in the `x-forwarded-for` header added by the outermost of
+    /// the proxies in front of the router. Each proxy appends the address it
+    /// received the request from, so entries further left can be set by the
+    /// client.
+    RemoteIp {
+        /// Number of proxies in front of the router that append to
+        /// `x-forwarded-for`, such as a load balancer.
+        #[serde(default = "default_trusted_proxies")]
+        trusted_proxies: usize,
+    },
+}
+
+fn default_trusted_proxies() -> usize {
+    1
+}
+
+impl ClientId {
+    /// Identifies the client sending a request. Requests that can't be
+    /// identified share a single budget.
+    pub fn identify(&self, headers: &HeaderMap) -> String {
+        let id = match self {
+            ClientId::Header(name) => header_str(headers, name).map(str::to_string),
+            ClientId::JwtClaim(claim) => header_str(headers, "authorization")
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .and_then(|token| jwt_claim(token, claim)),
+            ClientId::RemoteIp { trusted_proxies } => {
+                // a proxy may append a header line rather than an entry
+                let addresses: Vec<_> = headers
+                    .get_all("x-forwarded-for")
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .flat_map(|value| value.split(','))
+                    .map(str::trim)
+                    .collect();
+                addresses
+                    .len()
+                    .checked_sub(*trusted_proxies)
+                    .map(|i| addresses[i].to_string())
+            }
+        };
+
+        id.unwrap_or_default()
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn jwt_claim(token: &str, claim: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+
+    match claims.get(claim)? {
+        serde_json::Value::String(s) => Some(s.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Outcome of drawing an operation's cost from a client's budget.
+#[derive(Debug, PartialEq)]
+pub enum Debit {
+    Allowed {
+        remaining: usize,
+    },
+    Denied {
+        remaining: usize,
+        retry_after: Duration,
+    },
+}
+
+impl BucketLimits {
+    /// Checks that buckets can hold tokens and refill in a finite time.
+    fn validate(&self) -> Result<(), BoxError> {
+        if self.capacity == 0 {
+            return Err("budget capacity must be greater than 0".into());
+        }
+        if !(self.refill_per_second.is_finite() && self.refill_per_second > 0.0) {
+            return Err("budget refill_per_second must be a number greater than 0".into());
+        }
+        Ok(())
+    }
+
+    /// Outcome of a debit given the tokens in the bucket after refilling.
+    /// Returns the tokens left in the bucket along with it.
+    fn debit(&self, tokens: f64, cost: usize) -> (f64, Debit) {
+        let cost = cost as f64;
+        if cost <= tokens {
+            let tokens = tokens - cost;
+            (
+                tokens,
+                Debit::Allowed {
+                    remaining: tokens as usize,
+                },
+            )
+        } else {
+            (
+                tokens,
+                Debit::Denied {
+                    remaining: tokens as usize,
+                    retry_after: self.retry_after(tokens, cost),
+                },
+            )
+        }
+    }
+
+    fn retry_after(&self, tokens: f64, cost: f64) -> Duration {
+        // an operation costing more than the capacity can never succeed, so
+        // ask for a wait of a full refill at most
+        let missing = cost.min(self.capacity as f64) - tokens;
+        let full_refill = self.capacity as f64 / self.refill_per_second;
+        saturating_duration((missing / self.refill_per_second).min(full_refill))
+    }
+}
+
+/// Like `Duration::from_secs_f64`, but saturating instead of panicking for
+/// negative, NaN or out of range values.
+fn saturating_duration(secs: f64) -> Duration {
+    if secs.is_nan() || secs <= 0.0 {
+        Duration::ZERO
+    } else if secs >= u64::MAX as f64 {
+        Duration::from_secs(u64::MAX)
+    } else {
+        Duration::from_secs_f64(secs)
+    }
+}
+
+/// Storage for per-client token buckets.
+#[async_trait::async_trait]
+pub trait BudgetStore: std::fmt::Debug + Send + Sync {
+    /// Refills the client's bucket for the time elapsed since its last debit,
+    /// then draws `cost` from it if it has enough left. Otherwise the bucket
+    /// is left untouched.
+    async fn debit(&self, client: &str, cost: usize) -> Result<Debit, BoxError>;
+}
+
+/// Per-client cost budgets, as configured for the plugin.
+#[derive(Debug)]
+pub struct Budget {
+    client_id: ClientId,
+    store: Arc<dyn BudgetStore>,
+    on_store_error: OnStoreError,
+}
+
+impl Budget {
+    pub fn new(conf: &BudgetConf) -> Result<Self, BoxError> {
+        conf.limits.validate()?;
+        if let ClientId::RemoteIp { trusted_proxies: 0 } = conf.client_id {
+            return Err("budget client_id remote_ip trusted_proxies must be greater than 0".into());
+        }
+
+        let store: Arc<dyn BudgetStore> = match &conf.store {
+            StoreConf::Memory { max_clients } => {
+                if *max_clients == 0 {
+                    return Err("budget store max_clients must be greater than 0".into());
+                }
+                Arc::new(MemoryStore::new(conf.limits, *max_clients))
+            }
+            StoreConf::Redis {
+                url,
+                key_prefix,
+                timeout_ms,
+            } => Arc::new(RedisStore::new(
+                conf.limits,
+                url,
+                key_prefix,
+                Duration::from_millis(*timeout_ms),
+            )?),
+        };
+
+        Ok(Self {
+            client_id: conf.client_id.clone(),
+            store,
+            on_store_error: conf.on_store_error,
+        })
+    }
+
+    /// Identifies the client sending a request, see [`ClientId::identify`].
+    pub fn identify(&self, headers: &HeaderMap) -> String {
+        self.client_id.identify(headers)
+    }
+
+    pub async fn debit(&self, client: &str, cost: usize) -> Result<Debit, BoxError> {
+        self.store.debit(client, cost).await
+    }
+
+    pub fn on_store_error(&self) -> OnStoreError {
+        self.on_store_error
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use http::{HeaderMap, HeaderValue};
+
+    use super::{BucketLimits, Budget, BudgetConf, BudgetStore, ClientId, Debit, StoreConf};
+
+    /// Limits for [`check_store`]. The refill is slow enough not to affect
+    /// the checks.
+    pub(crate) const TEST_LIMITS: BucketLimits = BucketLimits {
+        capacity: 100,
+        refill_per_second: 0.001,
+    };
+
+    /// Checks the behaviour every store must have, starting from empty
+    /// buckets with [`TEST_LIMITS`]. `other` is a second handle on the same
+    /// buckets, as another router instance would have.
+    pub(crate) async fn check_store(store: &dyn BudgetStore, other: &dyn BudgetStore) {
+        assert_eq!(
+            store.debit("a", 80).await.unwrap(),
+            Debit::Allowed { remaining: 20 }
+        );
+        // denied debits leave the bucket untouched
+        assert!(matches!(
+            store.debit("a", 50).await.unwrap(),
+            Debit::Denied { remaining: 20, .. }
+        ));
+        // other clients have their own budget
+        assert_eq!(
+            store.debit("b", 50).await.unwrap(),
+            Debit::Allowed { remaining: 50 }
+        );
+        assert_eq!(
+            other.debit("a", 20).await.unwrap(),
+            Debit::Allowed { remaining: 0 }
+        );
+        assert!(matches!(
+            store.debit("a", 1).await.unwrap(),
+            Debit::Denied { remaining: 0, .. }
+        ));
+    }
+
+    fn conf(capacity: usize, refill_per_second: f64, max_clients: usize) -> BudgetConf {
+        BudgetConf {
+            limits: BucketLimits {
+                capacity,
+                refill_per_second,
+            },
+            client_id: ClientId::RemoteIp { trusted_proxies: 1 },
+            store: StoreConf::Memory { max_clients },
+            on_store_error: Default::default(),
+        }
+    }
+
+    #[test]
+    fn invalid_conf() {
+        assert!(Budget::new(&conf(100, 1.0, 10)).is_ok());
+        assert!(Budget::new(&conf(0, 1.0, 10)).is_err());
+        assert!(Budget::new(&conf(100, 0.0, 10)).is_err());
+        assert!(Budget::new(&conf(100, -1.0, 10)).is_err());
+        assert!(Budget::new(&conf(100, f64::NAN, 10)).is_err());
+        assert!(Budget::new(&conf(100, f64::INFINITY, 10)).is_err());
+        assert!(Budget::new(&conf(100, 1.0, 0)).is_err());
+
+        let mut no_proxies = conf(100, 1.0, 10);
+        no_proxies.client_id = ClientId::RemoteIp { trusted_proxies: 0 };
+        assert!(Budget::new(&no_proxies).is_err());
+    }
+
+    #[test]
+    fn retry_after() {
+        let limits = BucketLimits {
+            capacity: 100,
+            refill_per_second: 10.0,
+        };
+        assert_eq!(limits.retry_after(20.0, 50.0), Duration::from_secs(3));
+        // costs over the capacity wait for a full refill
+        assert_eq!(limits.retry_after(0.0, 1000.0), Duration::from_secs(10));
+
+        // refills too slow to represent saturate instead of panicking
+        let limits = BucketLimits {
+            capacity: usize::MAX,
+            refill_per_second: f64::MIN_POSITIVE,
+        };
+        assert!(matches!(
+            limits.debit(0.0, 1),
+            (_, Debit::Denied { retry_after, .. }) if retry_after == Duration::from_secs(u64::MAX)
+        ));
+    }
+
+    #[test]
+    fn identify() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client-id", HeaderValue::from_static("web"));
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.7, 10.0.0.1"),
+        );
+        // {"alg":"none"}.{"sub":"user-1","org":42}
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static(
+                "Bearer eyJhbGciOiJub25lIn0.eyJzdWIiOiJ1c2VyLTEiLCJvcmciOjQyfQ.",
+            ),
+        );
+
+        assert_eq!(
+            ClientId::Header("x-client-id".to_string()).identify(&headers),
+            "web"
+        );
+        assert_eq!(
+            ClientId::JwtClaim("sub".to_string()).identify(&headers),
+            "user-1"
+        );
+        assert_eq!(
+            ClientId::JwtClaim("org".to_string()).identify(&headers),
+            "42"
+        );
+        let remote_ip = |trusted_proxies| ClientId::RemoteIp { trusted_proxies };
+        assert_eq!(remote_ip(1).identify(&headers), "10.0.0.1");
+        assert_eq!(remote_ip(2).identify(&headers), "203.0.113.7");
+        // fewer addresses than proxies, so none can be trusted
+        assert_eq!(remote_ip(3).identify(&headers), "");
+        assert_eq!(
+            ClientId::Header("x-missing".to_string()).identify(&headers),
+            ""
+        );
+    }
+
+    #[test]
+    fn spoofed_forwarded_for() {
+        let remote_ip = ClientId::RemoteIp { trusted_proxies: 1 };
+
+        // the client's own header, with the load balancer's entry appended
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("198.51.100.1, 203.0.113.7"),
+        );
+        assert_eq!(remote_ip.identify(&headers), "203.0.113.7");
+
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("198.51.100.2, 203.0.113.7"),
+        );
+        assert_eq!(remote_ip.identify(&headers), "203.0.113.7");
+
+        // the load balancer's entry in a header line of its own
+        headers.insert("x-forwarded-for", HeaderValue::from_static("198.51.100.3"));
+        headers.append("x-forwarded-for", HeaderValue::from_static("203.0.113.7"));
+        assert_eq!(remote_ip.identify(&headers), "203.0.113.7");
+    }
+}
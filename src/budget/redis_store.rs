@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use redis::aio::ConnectionManager;
+use redis::{Client, Script};
+use tokio::sync::OnceCell;
+use tower::BoxError;
+
+use super::{BucketLimits, BudgetStore, Debit};
+
+/// Token buckets kept in Redis, so router instances sharing a Redis and a
+/// key prefix share budgets.
+///
+/// Each bucket is a hash holding its tokens and last update time. Debits run
+/// as a Lua script so concurrent debits from several routers can't
+/// interleave, and use the Redis clock so router clocks don't need to agree.
+pub struct RedisStore {
+    limits: BucketLimits,
+    client: Client,
+    connection: OnceCell<ConnectionManager>,
+    key_prefix: String,
+    timeout: Duration,
+    script: Script,
+}
+
+impl RedisStore {
+    /// Checks the URL but doesn't connect: the connection is made on the
+    /// first debit, so the router can start while Redis is down.
+    pub fn new(
+        limits: BucketLimits,
+        url: &str,
+        key_prefix: &str,
+        timeout: Duration,
+    ) -> Result<Self, BoxError> {
+        Ok(Self {
+            limits,
+            client: Client::open(url)?,
+            connection: OnceCell::new(),
+            key_prefix: key_prefix.to_string(),
+            timeout,
+            script: Script::new(include_str!("debit.lua")),
+        })
+    }
+
+    async fn try_debit(&self, client: &str, cost: usize) -> Result<Debit, BoxError> {
+        let mut connection = self
+            .connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await?
+            .clone();
+
+        let (allowed, tokens): (i64, String) = self
+            .script
+            .key(format!("{}{}", self.key_prefix, client))
+            .arg(self.limits.capacity)
+            .arg(self.limits.refill_per_second)
+            .arg(cost)
+            .invoke_async(&mut connection)
+            .await?;
+        let tokens: f64 = tokens.parse()?;
+
+        Ok(if allowed == 1 {
+            Debit::Allowed {
+                remaining: tokens as usize,
+            }
+        } else {
+            Debit::Denied {
+                remaining: tokens as usize,
+                retry_after: self.limits.retry_after(tokens, cost as f64),
+            }
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl BudgetStore for RedisStore {
+    async fn debit(&self, client: &str, cost: usize) -> Result<Debit, BoxError> {
+        tokio::time::timeout(self.timeout, self.try_debit(client, cost))
+            .await
+            .map_err(|_| "budget store timed out")?
+    }
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("limits", &self.limits)
+            .field("key_prefix", &self.key_prefix)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RedisStore;
+    use crate::budget::fake_redis::FakeRedis;
+    use crate::budget::tests::{check_store, TEST_LIMITS};
+    use crate::budget::{BucketLimits, BudgetStore, Debit};
+
+    fn redis_store(url: &str, key_prefix: &str) -> RedisStore {
+        RedisStore::new(TEST_LIMITS, url, key_prefix, Duration::from_secs(1)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn contract() {
+        let redis = FakeRedis::start().await;
+
+        // a second store with the same prefix shares the budgets
+        check_store(
+            &redis_store(redis.url(), "cost_budget:"),
+            &redis_store(redis.url(), "cost_budget:"),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis at REDIS_URL"]
+    async fn contract_with_redis() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
+        let key_prefix = format!("cost_budget_test:{}:", std::process::id());
+
+        check_store(
+            &redis_store(&url, &key_prefix),
+            &redis_store(&url, &key_prefix),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn refill_and_expiry() {
+        let redis = FakeRedis::start().await;
+        let store = RedisStore::new(
+            BucketLimits {
+                capacity: 10,
+                refill_per_second: 2.0,
+            },
+            redis.url(),
+            "cost_budget:",
+            Duration::from_secs(1),
+        )
+        .unwrap();
+
+        assert_eq!(
+            store.debit("a", 10).await.unwrap(),
+            Debit::Allowed { remaining: 0 }
+        );
+        assert_eq!(
+            store.debit("a", 4).await.unwrap(),
+            Debit::Denied {
+                remaining: 0,
+                retry_after: Duration::from_secs(2),
+            }
+        );
+
+        // buckets refill on the Redis clock
+        redis.advance(1.5);
+        assert_eq!(
+            store.debit("a", 2).await.unwrap(),
+            Debit::Allowed { remaining: 1 }
+        );
+
+        // and expire once they'd be full again
+        assert_eq!(redis.ttl("cost_budget:a"), Some(6.0));
+        redis.advance(6.0);
+        assert_eq!(redis.ttl("cost_budget:a"), None);
+        assert_eq!(
+            store.debit("a", 10).await.unwrap(),
+            Debit::Allowed { remaining: 0 }
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable() {
+        let store = redis_store("redis://127.0.0.1:1", "cost_budget:");
+
+        assert!(store.debit("a", 1).await.is_err());
+    }
+
+    #[test]
+    fn invalid_url() {
+        assert!(RedisStore::new(
+            BucketLimits {
+                capacity: 100,
+                refill_per_second: 1.0,
+            },
+            "not a url",
+            "cost_budget:",
+            Duration::from_secs(1),
+        )
+        .is_err());
+    }
+}
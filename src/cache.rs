@@ -0,0 +1,253 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use lru::LruCache;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CacheConf {
+    /// Number of analysis results to keep. 0 disables the cache.
+    pub capacity: usize,
+}
+
+impl Default for CacheConf {
+    fn default() -> Self {
+        Self { capacity: 1000 }
+    }
+}
+
+/// Bounded LRU cache of analysis results, keyed by the query, the operation
+/// name and the values of the variables the analysis read. Keys are SHA-256
+/// digests, so every entry takes the same memory however large the query,
+/// and a crafted query can't collide with a cheaper one and reuse its result.
+///
+/// Which variables matter is only known after analysing an operation once,
+/// so the cache remembers them per (query, operation name) and uses them to
+/// build the result key on later lookups. A cache belongs to a plugin
+/// instance, so it starts empty whenever the plugin is rebuilt for a new
+/// supergraph.
+pub struct AnalysisCache<V> {
+    inner: Option<Mutex<Inner<V>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Inner<V> {
+    used_variables: LruCache<OperationKey, Vec<String>>,
+    results: LruCache<ResultKey, V>,
+}
+
+/// Digest of the query and operation name.
+type OperationKey = [u8; 32];
+
+/// Digest of the operation key, and the name and serialized value of each
+/// variable the analysis read.
+type ResultKey = [u8; 32];
+
+impl<V: Clone> AnalysisCache<V> {
+    pub fn new(conf: &CacheConf) -> Self {
+        let inner = (conf.capacity > 0).then(|| {
+            Mutex::new(Inner {
+                used_variables: LruCache::new(conf.capacity),
+                results: LruCache::new(conf.capacity),
+            })
+        });
+
+        Self {
+            inner,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached result for the operation, or runs `analyze` and
+    /// caches what it returns. `analyze` returns the result together with
+    /// the names of the variables it read. Errors are not cached.
+    pub fn get_or_try_insert_with<E>(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+        variables: &Map<String, JsonValue>,
+        analyze: impl FnOnce() -> Result<(V, Vec<String>), E>,
+    ) -> Result<V, E> {
+        let inner = match &self.inner {
+            Some(inner) => inner,
+            None => return analyze().map(|(value, _)| value),
+        };
+
+        let operation_key = digest(&[Some(query.as_bytes()), operation_name.map(str::as_bytes)]);
+
+        {
+            let mut inner = inner.lock().expect("cache lock poisoned");
+            if let Some(used) = inner.used_variables.get(&operation_key).cloned() {
+                let result_key = result_key(&operation_key, &used, variables);
+                if let Some(value) = inner.results.get(&result_key) {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(value.clone());
+                }
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let (value, used) = analyze()?;
+
+        let mut inner = inner.lock().expect("cache lock poisoned");
+        let result_key = result_key(&operation_key, &used, variables);
+        inner.used_variables.put(operation_key, used);
+        inner.results.put(result_key, value.clone());
+
+        Ok(value)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl<V> std::fmt::Debug for AnalysisCache<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AnalysisCache")
+            .field("enabled", &self.inner.is_some())
+            .field("hits", &self.hits)
+            .field("misses", &self.misses)
+            .finish()
+    }
+}
+
+fn result_key(
+    operation_key: &OperationKey,
+    used: &[String],
+    variables: &Map<String, JsonValue>,
+) -> ResultKey {
+    let values: Vec<_> = used
+        .iter()
+        .map(|name| (name, variables.get(name).map(|value| value.to_string())))
+        .collect();
+
+    let mut parts = vec![Some(&operation_key[..])];
+    for (name, value) in &values {
+        parts.push(Some(name.as_bytes()));
+        parts.push(value.as_deref().map(str::as_bytes));
+    }
+    digest(&parts)
+}
+
+/// SHA-256 of `parts`, each marked as present or not and prefixed with its
+/// length, so that different parts never hash the same input.
+fn digest(parts: &[Option<&[u8]>]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        match part {
+            Some(bytes) => {
+                hasher.update([1u8]);
+                hasher.update((bytes.len() as u64).to_be_bytes());
+                hasher.update(bytes);
+            }
+            None => hasher.update([0u8]),
+        }
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map};
+
+    use super::{AnalysisCache, CacheConf};
+
+    fn analyze(value: usize) -> Result<(usize, Vec<String>), ()> {
+        Ok((value, vec!["n".to_string()]))
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let cache = AnalysisCache::new(&CacheConf { capacity: 10 });
+
+        let mut variables = Map::new();
+        variables.insert("n".to_string(), json!(1));
+        variables.insert("id".to_string(), json!("a"));
+
+        assert_eq!(
+            cache.get_or_try_insert_with("{ a }", None, &variables, || analyze(1)),
+            Ok(1)
+        );
+        assert_eq!(
+            cache.get_or_try_insert_with("{ a }", None, &variables, || analyze(2)),
+            Ok(1)
+        );
+
+        // variables the analysis didn't read don't affect the key
+        variables.insert("id".to_string(), json!("b"));
+        assert_eq!(
+            cache.get_or_try_insert_with("{ a }", None, &variables, || analyze(3)),
+            Ok(1)
+        );
+
+        variables.insert("n".to_string(), json!(2));
+        assert_eq!(
+            cache.get_or_try_insert_with("{ a }", None, &variables, || analyze(4)),
+            Ok(4)
+        );
+
+        assert_eq!(
+            cache.get_or_try_insert_with("{ a }", Some("A"), &variables, || analyze(5)),
+            Ok(5)
+        );
+
+        assert_eq!(
+            cache.get_or_try_insert_with("{ b }", Some("A"), &variables, || analyze(6)),
+            Ok(6)
+        );
+
+        // no operation name isn't the same as an empty one
+        assert_eq!(
+            cache.get_or_try_insert_with("{ b }", Some(""), &variables, || analyze(7)),
+            Ok(7)
+        );
+        assert_eq!(
+            cache.get_or_try_insert_with("{ b }", None, &variables, || analyze(8)),
+            Ok(8)
+        );
+
+        assert_eq!(cache.hits(), 2);
+        assert_eq!(cache.misses(), 6);
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let cache = AnalysisCache::new(&CacheConf { capacity: 10 });
+
+        assert_eq!(
+            cache.get_or_try_insert_with("{ a }", None, &Map::new(), || Err::<(usize, _), _>(())),
+            Err(())
+        );
+        assert_eq!(
+            cache.get_or_try_insert_with("{ a }", None, &Map::new(), || analyze(1)),
+            Ok(1)
+        );
+        assert_eq!(cache.misses(), 2);
+    }
+
+    #[test]
+    fn disabled() {
+        let cache = AnalysisCache::new(&CacheConf { capacity: 0 });
+
+        assert_eq!(
+            cache.get_or_try_insert_with("{ a }", None, &Map::new(), || analyze(1)),
+            Ok(1)
+        );
+        assert_eq!(
+            cache.get_or_try_insert_with("{ a }", None, &Map::new(), || analyze(2)),
+            Ok(2)
+        );
+        assert_eq!(cache.hits(), 0);
+    }
+}
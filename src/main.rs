@@ -1,6 +1,5 @@
-mod compiler_ext;
-mod operation_cost;
-mod operation_depth;
+mod budget;
+mod cache;
 mod plugins;
 
 use anyhow::Result;
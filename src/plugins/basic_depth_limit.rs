@@ -1,28 +1,78 @@
 use std::ops::ControlFlow;
+use std::sync::Arc;
 
-use apollo_compiler::ApolloCompiler;
-use apollo_router::graphql::Error;
 use apollo_router::layers::ServiceBuilderExt;
 use apollo_router::plugin::{Plugin, PluginInit};
 use apollo_router::register_plugin;
 use apollo_router::services::supergraph;
 use http::StatusCode;
+use operation_analysis::operation_depth::operation_depth;
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tower::util::BoxService;
 use tower::{BoxError, ServiceBuilder, ServiceExt};
 
-use crate::compiler_ext::CompilerAdditions;
-use crate::operation_depth::OperationDefinitionExt;
+use crate::cache::{AnalysisCache, CacheConf};
+
+use super::metrics::{MetricsConf, OperationHistogram, Outcome, RequestAttributes};
+use super::rejection::{AnalysisErrors, Mode, OnError, Rejection, RejectionConf};
+use super::warning::{with_warnings, Warning};
+
+/// Context key under which the analysis is published as an
+/// [`OperationDepthResult`], for other plugins.
+pub(crate) const OPERATION_DEPTH_CONTEXT_KEY: &str = "apollosolutions.operation_depth";
+
+/// Depth analysis of a request's operation, as published in the context.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OperationDepthResult {
+    pub(crate) depth: usize,
+    pub(crate) limit: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) warn_at: Option<usize>,
+}
 
 #[derive(Debug)]
 struct BasicDepthLimit {
     configuration: Conf,
+    cache: Arc<AnalysisCache<usize>>,
+    limit_exceeded: Rejection,
+    analysis_errors: AnalysisErrors,
+    approaching_limit: Warning,
+    depth_histogram: OperationHistogram,
+    request_attributes: RequestAttributes,
 }
 
 #[derive(Debug, Default, Deserialize, JsonSchema)]
 struct Conf {
     limit: usize,
+    /// Operations deeper than this execute, but get a warning. Disabled if
+    /// not set.
+    warn_at: Option<usize>,
+    /// `measure` lets requests that would be rejected through.
+    #[serde(default)]
+    mode: Mode,
+    /// What to do with requests whose depth can't be measured.
+    #[serde(default)]
+    on_error: OnError,
+    #[serde(default)]
+    cache: CacheConf,
+    #[serde(default)]
+    errors: ErrorsConf,
+    #[serde(default)]
+    metrics: MetricsConf,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+struct ErrorsConf {
+    /// Returned when the depth exceeds `limit`.
+    limit_exceeded: RejectionConf,
+    /// Returned when the depth can't be measured because the operation is
+    /// invalid.
+    invalid_operation: RejectionConf,
+    /// Returned when the depth can't be measured for other reasons.
+    calculation_failed: RejectionConf,
 }
 
 #[async_trait::async_trait]
@@ -31,6 +81,40 @@ impl Plugin for BasicDepthLimit {
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
         Ok(BasicDepthLimit {
+            limit_exceeded: Rejection::new(
+                "DEPTH_LIMIT_EXCEEDED",
+                "operation depth exceeded limit",
+                StatusCode::BAD_REQUEST,
+                &init.config.errors.limit_exceeded,
+                init.config.mode,
+            )?,
+            analysis_errors: AnalysisErrors::new(
+                init.config.on_error,
+                Rejection::new(
+                    "DEPTH_INVALID_OPERATION",
+                    "could not measure the depth of an invalid operation",
+                    StatusCode::BAD_REQUEST,
+                    &init.config.errors.invalid_operation,
+                    init.config.mode,
+                )?,
+                Rejection::new(
+                    "DEPTH_CALCULATION_FAILED",
+                    "could not measure operation depth",
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &init.config.errors.calculation_failed,
+                    init.config.mode,
+                )?,
+            ),
+            approaching_limit: Warning::new(
+                "DEPTH_APPROACHING_LIMIT",
+                "operation depth is approaching the limit",
+            ),
+            depth_histogram: OperationHistogram::new(
+                "apollosolutions.operation.depth",
+                "Depth of operations, as calculated by the depth plugin",
+            ),
+            request_attributes: RequestAttributes::new(&init.config.metrics)?,
+            cache: Arc::new(AnalysisCache::new(&init.config.cache)),
             configuration: init.config,
         })
     }
@@ -40,32 +124,95 @@ impl Plugin for BasicDepthLimit {
         service: BoxService<supergraph::Request, supergraph::Response, BoxError>,
     ) -> BoxService<supergraph::Request, supergraph::Response, BoxError> {
         let limit = self.configuration.limit;
+        let cache = self.cache.clone();
+        let warn_at = self.configuration.warn_at;
+        let limit_exceeded = self.limit_exceeded.clone();
+        let analysis_errors = self.analysis_errors.clone();
+        let approaching_limit = self.approaching_limit.clone();
+        let depth_histogram = self.depth_histogram.clone();
+        let request_attributes = self.request_attributes.clone();
         ServiceBuilder::new()
+            .instrument(|_: &supergraph::Request| {
+                tracing::info_span!("operation_depth", "operation.depth" = tracing::field::Empty)
+            })
+            .map_response(with_warnings)
             .checkpoint(move |req: supergraph::Request| {
                 if let Some(operation) = req.supergraph_request.body().query.clone() {
-                    let ctx = ApolloCompiler::new(&operation);
                     let operation_name = req.supergraph_request.body().operation_name.as_deref();
+                    let variables =
+                        match serde_json::to_value(&req.supergraph_request.body().variables)? {
+                            serde_json::Value::Object(variables) => variables,
+                            _ => Default::default(),
+                        };
 
-                    if let Some(operation) = ctx.operation_by_name(operation_name) {
-                        let depth = operation.max_depth(&ctx);
+                    let result = cache.get_or_try_insert_with(
+                        &operation,
+                        operation_name,
+                        &variables,
+                        || {
+                            operation_depth(&operation, operation_name, &variables)
+                                .map(|analysis| (analysis.depth, analysis.variables))
+                        },
+                    );
 
-                        tracing::debug!(?operation_name, %depth, "operation_depth");
+                    let attributes = request_attributes.of(&req);
+                    match result {
+                        Ok(depth) => {
+                            tracing::debug!(
+                                ?operation_name,
+                                %depth,
+                                cache_hits = cache.hits(),
+                                cache_misses = cache.misses(),
+                                "operation_depth"
+                            );
+                            tracing::Span::current().record("operation.depth", &depth);
+                            req.context.insert(
+                                OPERATION_DEPTH_CONTEXT_KEY,
+                                OperationDepthResult {
+                                    depth,
+                                    limit,
+                                    warn_at,
+                                },
+                            )?;
 
-                        if depth > limit {
-                            let error = Error::builder()
-                                .message("operation depth exceeded limit")
-                                .build();
+                            let mut outcome = Outcome::Accepted;
+                            if depth > limit {
+                                if let Some(res) = limit_exceeded.reject(
+                                    &req.context,
+                                    &attributes,
+                                    &[("measured", depth), ("limit", limit)],
+                                )? {
+                                    depth_histogram.record(depth, &attributes, Outcome::Rejected);
 
-                            let res = supergraph::Response::builder()
-                                .error(error)
-                                .status_code(StatusCode::BAD_REQUEST)
-                                .context(req.context)
-                                .build()?;
+                                    return Ok(ControlFlow::Break(res));
+                                }
+                                outcome = Outcome::WouldReject;
+                            }
 
-                            return Ok(ControlFlow::Break(res));
+                            if let Some(warn_at) = warn_at {
+                                if depth > warn_at {
+                                    approaching_limit.warn(
+                                        &req.context,
+                                        &[
+                                            ("measured", depth),
+                                            ("threshold", warn_at),
+                                            ("limit", limit),
+                                        ],
+                                    )?;
+                                    if outcome == Outcome::Accepted {
+                                        outcome = Outcome::Warned;
+                                    }
+                                }
+                            }
+                            depth_histogram.record(depth, &attributes, outcome);
+                        }
+                        Err(err) => {
+                            if let Some(res) =
+                                analysis_errors.reject(&err, &req.context, &attributes)?
+                            {
+                                return Ok(ControlFlow::Break(res));
+                            }
                         }
-                    } else {
-                        tracing::warn!("could not find operation in document");
                     }
                 }
 
@@ -87,6 +234,8 @@ mod tests {
     use tower::BoxError;
     use tower::ServiceExt;
 
+    use super::{OperationDepthResult, OPERATION_DEPTH_CONTEXT_KEY};
+
     #[tokio::test]
     async fn plugin_registered() {
         let config = serde_json::json!({
@@ -132,4 +281,178 @@ mod tests {
         assert!(next.is_none());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn basic_test_error_response() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+            "plugins": {
+                "apollosolutions.basic_depth_limit": {
+                    "limit" : 2,
+                }
+            }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        assert!(first_response.data.is_none());
+        let error = first_response.errors.first().expect("qed");
+        assert_eq!(error.message, "operation depth exceeded limit");
+        assert_eq!(
+            error.extensions.get("code"),
+            Some(&"DEPTH_LIMIT_EXCEEDED".into())
+        );
+        assert_eq!(error.extensions.get("limit"), Some(&2.into()));
+        assert_eq!(error.extensions.get("measured"), Some(&4.into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn context() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+            "plugins": {
+                "apollosolutions.basic_depth_limit": {
+                    "limit" : 10,
+                    "warn_at": 8,
+                }
+            }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let streamed_response = test_harness.oneshot(request).await?;
+
+        let result = streamed_response
+            .context
+            .get::<_, OperationDepthResult>(OPERATION_DEPTH_CONTEXT_KEY)?
+            .expect("missing operation depth");
+        assert_eq!(result.depth, 4);
+        assert_eq!(result.limit, 10);
+        assert_eq!(result.warn_at, Some(8));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn measure_mode() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+            "plugins": {
+                "apollosolutions.basic_depth_limit": {
+                    "limit" : 2,
+                    "mode": "measure",
+                }
+            }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+        assert_eq!(
+            streamed_response
+                .context
+                .get::<_, Vec<String>>("apollosolutions.would_reject")?,
+            Some(vec!["DEPTH_LIMIT_EXCEEDED".to_string()])
+        );
+
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        assert!(first_response.data.is_some());
+        assert!(first_response.errors.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn warn_at() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+            "plugins": {
+                "apollosolutions.basic_depth_limit": {
+                    "limit" : 10,
+                    "warn_at": 3,
+                }
+            }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        assert!(first_response.data.is_some());
+        let warnings = first_response
+            .extensions
+            .get("warnings")
+            .and_then(|warnings| warnings.as_array())
+            .expect("missing warnings extension");
+        let extensions = warnings[0].get("extensions").expect("qed");
+        assert_eq!(
+            extensions.get("code"),
+            Some(&"DEPTH_APPROACHING_LIMIT".into())
+        );
+        assert_eq!(extensions.get("measured"), Some(&4.into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn on_error() -> Result<(), BoxError> {
+        let code = |on_error| async move {
+            let test_harness = TestHarness::builder()
+                .configuration_json(serde_json::json!({
+                    "plugins": {
+                        "apollosolutions.basic_depth_limit": {
+                            "limit" : 10,
+                            "on_error": on_error,
+                        }
+                    }
+                }))
+                .unwrap()
+                .build()
+                .await
+                .unwrap();
+
+            let request = supergraph::Request::canned_builder()
+                .operation_name("Missing")
+                .build()
+                .unwrap();
+            let first_response = test_harness
+                .oneshot(request)
+                .await
+                .unwrap()
+                .next_response()
+                .await
+                .expect("couldn't get primary response");
+            first_response
+                .errors
+                .first()
+                .and_then(|error| error.extensions.get("code").cloned())
+        };
+
+        assert_eq!(code("reject").await, Some("DEPTH_INVALID_OPERATION".into()));
+        // the router reports the missing operation itself
+        assert_ne!(code("allow").await, Some("DEPTH_INVALID_OPERATION".into()));
+        Ok(())
+    }
 }
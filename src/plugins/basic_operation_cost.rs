@@ -1,30 +1,209 @@
-use http::StatusCode;
+use http::header::RETRY_AFTER;
+use http::{HeaderValue, StatusCode};
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::ops::ControlFlow;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use apollo_router::graphql::Error;
 use apollo_router::layers::ServiceBuilderExt;
 use apollo_router::plugin::{Plugin, PluginInit};
 use apollo_router::register_plugin;
 use apollo_router::services::supergraph;
+use operation_analysis::cost_script::CostScript;
+use operation_analysis::operation_cost::{
+    operation_cost, validate_cost_map, Cost, CostAnalysis, FieldCost, ListSizeConf,
+};
+use operation_analysis::schema::Schema;
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tower::util::BoxService;
 use tower::{BoxError, ServiceBuilder, ServiceExt};
 
-use crate::operation_cost::{operation_cost, Cost};
+use crate::budget::{Budget, BudgetConf, Debit, OnStoreError};
+use crate::cache::{AnalysisCache, CacheConf};
+
+use super::cost_map_file::CostMapFile;
+use super::extension_from_context;
+use super::metrics::{MetricsConf, OperationHistogram, Outcome, RequestAttributes};
+use super::rejection::{AnalysisErrors, Mode, OnError, Rejection, RejectionConf};
+use super::warning::{with_warnings, Warning};
+
+/// Context key under which the explain extension is passed from the request
+/// checkpoint to the response.
+const EXPLAIN_CONTEXT_KEY: &str = "apollosolutions.basic_operation_cost.explain";
+/// Context key under which the analysis is published as an
+/// [`OperationCostResult`], for the budget debit and other plugins.
+pub(crate) const OPERATION_COST_CONTEXT_KEY: &str = "apollosolutions.operation_cost";
+/// Number of coordinates recorded on the span.
+const SPAN_TOP_COORDINATES: usize = 5;
+/// How often `cost_map_file` is checked for changes.
+const COST_MAP_FILE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Cost analysis of a request's operation, as published in the context.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OperationCostResult {
+    pub(crate) cost: Cost,
+    pub(crate) max_cost: Cost,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) warn_at: Option<Cost>,
+    pub(crate) breakdown: Vec<FieldCost>,
+}
 
 #[derive(Debug)]
 struct BasicOperationCost {
     configuration: Conf,
-    sdl: Arc<String>,
+    schema: Arc<Schema>,
+    weights: Arc<RwLock<Arc<Weights>>>,
+    script: Option<Arc<CostScript>>,
+    budget: Option<Arc<Budget>>,
+    limit_exceeded: Rejection,
+    analysis_errors: AnalysisErrors,
+    budget_exceeded: Rejection,
+    budget_unavailable: Rejection,
+    approaching_limit: Warning,
+    cost_histogram: OperationHistogram,
+    request_attributes: RequestAttributes,
 }
 
 #[derive(Debug, Default, Deserialize, JsonSchema)]
 struct Conf {
+    #[serde(default)]
     cost_map: HashMap<String, usize>,
+    /// YAML or JSON file with more `cost_map` entries, reloaded when it
+    /// changes. Entries in `cost_map` take precedence.
+    cost_map_file: Option<PathBuf>,
+    /// SDL files whose `@cost` and `@listSize` directives are merged over
+    /// the supergraph's.
+    #[serde(default)]
+    overlays: Vec<PathBuf>,
+    /// What to do when `cost_map` or `overlays` have entries that don't
+    /// match a type or field of the supergraph.
+    #[serde(default)]
+    on_invalid_cost_map: OnInvalidCostMap,
     max_cost: usize,
+    /// Operations costing more than this execute, but get a warning.
+    /// Disabled if not set.
+    warn_at: Option<usize>,
+    /// `measure` lets requests that would be rejected through.
+    #[serde(default)]
+    mode: Mode,
+    /// What to do with requests whose cost can't be calculated.
+    #[serde(default)]
+    on_error: OnError,
+    #[serde(default)]
+    list_size: ListSizeConf,
+    #[serde(default)]
+    cache: CacheConf,
+    /// Rhai script with a `field_cost(field)` function adjusting the cost of
+    /// fields. Disabled if not set.
+    script: Option<PathBuf>,
+    /// Attach the cost breakdown to responses when requested. Disabled if
+    /// not set.
+    explain: Option<ExplainConf>,
+    /// Per-client cost budgets. Disabled if not set.
+    budget: Option<BudgetConf>,
+    #[serde(default)]
+    errors: ErrorsConf,
+    #[serde(default)]
+    metrics: MetricsConf,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum OnInvalidCostMap {
+    /// Fail to start the plugin.
+    Fail,
+    /// Log a warning and ignore the entries.
+    Warn,
+}
+
+impl Default for OnInvalidCostMap {
+    fn default() -> Self {
+        OnInvalidCostMap::Fail
+    }
+}
+
+impl OnInvalidCostMap {
+    /// Fails with `err`, or logs it, depending on the policy.
+    fn report(self, err: impl Display) -> Result<(), BoxError> {
+        match self {
+            OnInvalidCostMap::Fail => Err(err.to_string().into()),
+            OnInvalidCostMap::Warn => {
+                tracing::warn!("{}", err);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The cost map in effect and the analyses made with it. Replaced as a whole
+/// when `cost_map_file` changes, so cached results never outlive the weights
+/// they were calculated with.
+#[derive(Debug)]
+struct Weights {
+    cost_map: HashMap<String, usize>,
+    cache: AnalysisCache<Arc<CostAnalysis>>,
+}
+
+impl Weights {
+    /// Merges the inline `cost_map` over the file's entries and validates the
+    /// result.
+    fn new(
+        schema: &Schema,
+        inline: &HashMap<String, usize>,
+        mut cost_map: HashMap<String, usize>,
+        on_invalid: OnInvalidCostMap,
+        cache: &CacheConf,
+    ) -> Result<Self, BoxError> {
+        cost_map.extend(inline.clone());
+        if let Err(err) = validate_cost_map(schema, &cost_map) {
+            on_invalid.report(err)?;
+        }
+
+        Ok(Self {
+            cost_map,
+            cache: AnalysisCache::new(cache),
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+struct ErrorsConf {
+    /// Returned when the cost exceeds `max_cost`.
+    limit_exceeded: RejectionConf,
+    /// Returned when the cost can't be calculated because the operation is
+    /// invalid.
+    invalid_operation: RejectionConf,
+    /// Returned when the cost can't be calculated for other reasons.
+    calculation_failed: RejectionConf,
+    /// Returned when the client's budget can't cover the cost.
+    budget_exceeded: RejectionConf,
+    /// Returned when the budget store can't be reached and
+    /// `budget.on_store_error` is `reject`.
+    budget_unavailable: RejectionConf,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(default)]
+struct ExplainConf {
+    /// Request header that asks for the cost breakdown.
+    header: String,
+    /// If set, the header value must match it. Otherwise the value must be
+    /// `true`.
+    secret: Option<String>,
+}
+
+impl Default for ExplainConf {
+    fn default() -> Self {
+        Self {
+            header: "x-cost-explain".to_string(),
+            secret: None,
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -32,9 +211,118 @@ impl Plugin for BasicOperationCost {
     type Config = Conf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let mut schema = Schema::new(&init.supergraph_sdl);
+        for path in &init.config.overlays {
+            let overlay = std::fs::read_to_string(path)
+                .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+            if let Err(err) = schema.apply_overlay(&overlay) {
+                init.config.on_invalid_cost_map.report(format!(
+                    "invalid overlay {}: {}",
+                    path.display(),
+                    err
+                ))?;
+            }
+        }
+        let schema = Arc::new(schema);
+
+        let (cost_map_file, file_cost_map) = match &init.config.cost_map_file {
+            Some(path) => {
+                let (file, cost_map) = CostMapFile::read(path)?;
+                (Some(file), cost_map)
+            }
+            None => (None, HashMap::new()),
+        };
+        let weights = Arc::new(RwLock::new(Arc::new(Weights::new(
+            &schema,
+            &init.config.cost_map,
+            file_cost_map,
+            init.config.on_invalid_cost_map,
+            &init.config.cache,
+        )?)));
+
+        if let Some(file) = cost_map_file {
+            let schema = schema.clone();
+            let inline = init.config.cost_map.clone();
+            let on_invalid = init.config.on_invalid_cost_map;
+            let cache = init.config.cache.clone();
+            file.watch(
+                COST_MAP_FILE_POLL_INTERVAL,
+                Arc::downgrade(&weights),
+                move |weights, file_cost_map| match Weights::new(
+                    &schema,
+                    &inline,
+                    file_cost_map,
+                    on_invalid,
+                    &cache,
+                ) {
+                    Ok(new_weights) => {
+                        *weights.write().expect("weights lock poisoned") = Arc::new(new_weights);
+                        tracing::info!("reloaded cost_map_file");
+                    }
+                    Err(err) => tracing::error!("keeping the previous cost map: {}", err),
+                },
+            );
+        }
+
         Ok(BasicOperationCost {
+            limit_exceeded: Rejection::new(
+                "COST_LIMIT_EXCEEDED",
+                "operation cost exceeded limit",
+                StatusCode::BAD_REQUEST,
+                &init.config.errors.limit_exceeded,
+                init.config.mode,
+            )?,
+            analysis_errors: AnalysisErrors::new(
+                init.config.on_error,
+                Rejection::new(
+                    "COST_INVALID_OPERATION",
+                    "could not calculate the cost of an invalid operation",
+                    StatusCode::BAD_REQUEST,
+                    &init.config.errors.invalid_operation,
+                    init.config.mode,
+                )?,
+                Rejection::new(
+                    "COST_CALCULATION_FAILED",
+                    "could not calculate operation cost",
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &init.config.errors.calculation_failed,
+                    init.config.mode,
+                )?,
+            ),
+            budget_exceeded: Rejection::new(
+                "COST_BUDGET_EXCEEDED",
+                "operation cost exceeded remaining budget",
+                StatusCode::TOO_MANY_REQUESTS,
+                &init.config.errors.budget_exceeded,
+                init.config.mode,
+            )?,
+            budget_unavailable: Rejection::new(
+                "COST_BUDGET_UNAVAILABLE",
+                "could not check operation cost against budget",
+                StatusCode::SERVICE_UNAVAILABLE,
+                &init.config.errors.budget_unavailable,
+                init.config.mode,
+            )?,
+            approaching_limit: Warning::new(
+                "COST_APPROACHING_LIMIT",
+                "operation cost is approaching the limit",
+            ),
+            cost_histogram: OperationHistogram::new(
+                "apollosolutions.operation.cost",
+                "Cost of operations, as calculated by the cost plugin",
+            ),
+            request_attributes: RequestAttributes::new(&init.config.metrics)?,
+            budget: match &init.config.budget {
+                Some(budget) => Some(Arc::new(Budget::new(budget)?)),
+                None => None,
+            },
+            script: match &init.config.script {
+                Some(path) => Some(Arc::new(CostScript::new(path)?)),
+                None => None,
+            },
+            schema,
+            weights,
             configuration: init.config,
-            sdl: init.supergraph_sdl,
         })
     }
 
@@ -42,54 +330,240 @@ impl Plugin for BasicOperationCost {
         &self,
         service: BoxService<supergraph::Request, supergraph::Response, BoxError>,
     ) -> BoxService<supergraph::Request, supergraph::Response, BoxError> {
-        let sdl = self.sdl.clone();
-        let cost_map = self.configuration.cost_map.clone();
+        let schema = self.schema.clone();
+        let weights = self.weights.clone();
+        let script = self.script.clone();
+        let list_size = self.configuration.list_size.clone();
         let max_cost = Cost::new(self.configuration.max_cost);
+        let warn_at = self.configuration.warn_at.map(Cost::new);
+        let explain = self.configuration.explain.clone();
+        let limit_exceeded = self.limit_exceeded.clone();
+        let analysis_errors = self.analysis_errors.clone();
+        let approaching_limit = self.approaching_limit.clone();
+        let cost_histogram = self.cost_histogram.clone();
+        let budget_exceeded = self.budget_exceeded.clone();
+        let budget_unavailable = self.budget_unavailable.clone();
+        let budget = self.budget.clone();
+        let request_attributes = self.request_attributes.clone();
+        let budget_attributes = self.request_attributes.clone();
 
         ServiceBuilder::new()
+            .instrument(|_: &supergraph::Request| {
+                tracing::info_span!(
+                    "operation_cost",
+                    "operation.cost" = tracing::field::Empty,
+                    "operation.cost.top_coordinates" = tracing::field::Empty,
+                )
+            })
+            .map_response(|res: supergraph::Response| {
+                extension_from_context(with_warnings(res), EXPLAIN_CONTEXT_KEY, "cost")
+            })
             .checkpoint(move |req: supergraph::Request| {
                 if let Some(operation) = req.supergraph_request.body().query.clone() {
                     let operation_name = req.supergraph_request.body().operation_name.as_deref();
-                    let result = operation_cost(&sdl, &operation, operation_name, &cost_map);
+                    let variables =
+                        match serde_json::to_value(&req.supergraph_request.body().variables)? {
+                            serde_json::Value::Object(variables) => variables,
+                            _ => Default::default(),
+                        };
+                    let weights = weights.read().expect("weights lock poisoned").clone();
+                    let result = weights.cache.get_or_try_insert_with(
+                        &operation,
+                        operation_name,
+                        &variables,
+                        || {
+                            operation_cost(
+                                &schema,
+                                &operation,
+                                operation_name,
+                                &variables,
+                                &weights.cost_map,
+                                &list_size,
+                                script.as_deref(),
+                            )
+                            .map(|analysis| {
+                                let variables = analysis.variables.clone();
+                                (Arc::new(analysis), variables)
+                            })
+                        },
+                    );
+                    let attributes = request_attributes.of(&req);
 
-                    if let Ok(cost) = result {
-                        tracing::debug!(?operation_name, %cost, "operation_cost");
+                    match result {
+                        Ok(analysis) => {
+                            let cost = &analysis.cost;
+                            tracing::debug!(
+                                ?operation_name,
+                                %cost,
+                                cache_hits = weights.cache.hits(),
+                                cache_misses = weights.cache.misses(),
+                                "operation_cost"
+                            );
 
-                        if cost > max_cost {
-                            let error = Error::builder()
-                                .message("operation cost exceeded limit")
-                                .build();
+                            let span = tracing::Span::current();
+                            span.record("operation.cost", &cost.get());
+                            if !span.is_disabled() {
+                                let top_coordinates = analysis
+                                    .top_coordinates(SPAN_TOP_COORDINATES)
+                                    .iter()
+                                    .map(|(coordinate, cost)| format!("{}={}", coordinate, cost))
+                                    .collect::<Vec<_>>()
+                                    .join(",");
+                                span.record(
+                                    "operation.cost.top_coordinates",
+                                    &top_coordinates.as_str(),
+                                );
+                            }
 
-                            let res = supergraph::Response::builder()
-                                .error(error)
-                                .status_code(StatusCode::BAD_REQUEST)
-                                .context(req.context)
-                                .build()?;
+                            let explain_requested = explain.as_ref().map_or(false, |explain| {
+                                req.supergraph_request
+                                    .headers()
+                                    .get(&explain.header)
+                                    .map_or(false, |value| {
+                                        constant_time_eq(
+                                            value.as_bytes(),
+                                            explain.secret.as_deref().unwrap_or("true").as_bytes(),
+                                        )
+                                    })
+                            });
+                            let published = OperationCostResult {
+                                cost: cost.clone(),
+                                max_cost: max_cost.clone(),
+                                warn_at: warn_at.clone(),
+                                breakdown: analysis.breakdown.clone(),
+                            };
+                            if explain_requested {
+                                req.context.insert(EXPLAIN_CONTEXT_KEY, published.clone())?;
+                            }
+                            req.context.insert(OPERATION_COST_CONTEXT_KEY, published)?;
 
-                            return Ok(ControlFlow::Break(res));
+                            let mut outcome = Outcome::Accepted;
+                            if *cost > max_cost {
+                                tracing::debug!(
+                                    ?operation_name,
+                                    breakdown = ?analysis.breakdown,
+                                    "operation_cost_breakdown"
+                                );
+
+                                if let Some(res) = limit_exceeded.reject(
+                                    &req.context,
+                                    &attributes,
+                                    &[("measured", cost.get()), ("limit", max_cost.get())],
+                                )? {
+                                    cost_histogram.record(
+                                        cost.get(),
+                                        &attributes,
+                                        Outcome::Rejected,
+                                    );
+
+                                    return Ok(ControlFlow::Break(res));
+                                }
+                                outcome = Outcome::WouldReject;
+                            }
+
+                            if let Some(warn_at) = &warn_at {
+                                if cost > warn_at {
+                                    approaching_limit.warn(
+                                        &req.context,
+                                        &[
+                                            ("measured", cost.get()),
+                                            ("threshold", warn_at.get()),
+                                            ("limit", max_cost.get()),
+                                        ],
+                                    )?;
+                                    if outcome == Outcome::Accepted {
+                                        outcome = Outcome::Warned;
+                                    }
+                                }
+                            }
+                            cost_histogram.record(cost.get(), &attributes, outcome);
+                        }
+                        Err(err) => {
+                            if let Some(res) =
+                                analysis_errors.reject(&err, &req.context, &attributes)?
+                            {
+                                return Ok(ControlFlow::Break(res));
+                            }
                         }
-                    } else {
-                        let error = Error::builder()
-                            .message("could not calculate operation cost")
-                            .build();
-
-                        let res = supergraph::Response::builder()
-                            .error(error)
-                            .status_code(StatusCode::INTERNAL_SERVER_ERROR)
-                            .context(req.context)
-                            .build()?;
-
-                        return Ok(ControlFlow::Break(res));
                     }
                 }
 
                 Ok(ControlFlow::Continue(req))
             })
+            .checkpoint_async(move |req: supergraph::Request| {
+                let budget = budget.clone();
+                let budget_exceeded = budget_exceeded.clone();
+                let budget_unavailable = budget_unavailable.clone();
+                let budget_attributes = budget_attributes.clone();
+
+                async move {
+                    let budget = match budget {
+                        Some(budget) => budget,
+                        None => return Ok(ControlFlow::Continue(req)),
+                    };
+                    let cost = match req
+                        .context
+                        .get::<_, OperationCostResult>(OPERATION_COST_CONTEXT_KEY)?
+                    {
+                        Some(result) => result.cost.get(),
+                        None => return Ok(ControlFlow::Continue(req)),
+                    };
+
+                    let client = budget.identify(req.supergraph_request.headers());
+                    let attributes = budget_attributes.of(&req);
+                    match budget.debit(&client, cost).await {
+                        Ok(Debit::Allowed { .. }) => {}
+                        Ok(Debit::Denied {
+                            remaining,
+                            retry_after,
+                        }) => {
+                            // round up so clients don't retry too early
+                            let retry_after =
+                                retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                            tracing::debug!(%client, %cost, %remaining, %retry_after, "operation_cost_budget_exceeded");
+
+                            if let Some(mut res) = budget_exceeded.reject(
+                                &req.context,
+                                &attributes,
+                                &[
+                                    ("measured", cost),
+                                    ("remaining", remaining),
+                                    ("retryAfter", retry_after as usize),
+                                ],
+                            )? {
+                                res.response
+                                    .headers_mut()
+                                    .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+
+                                return Ok(ControlFlow::Break(res));
+                            }
+                        }
+                        Err(err) => {
+                            tracing::warn!(%client, %err, "operation_cost_budget_unavailable");
+
+                            if budget.on_store_error() == OnStoreError::Reject {
+                                if let Some(res) = budget_unavailable.reject(&req.context, &attributes, &[])? {
+                                    return Ok(ControlFlow::Break(res));
+                                }
+                            }
+                        }
+                    }
+
+                    Ok(ControlFlow::Continue(req))
+                }
+            })
+            .buffered()
             .service(service)
             .boxed()
     }
 }
 
+/// Compares the explain header to the secret without returning early, so the
+/// response time doesn't reveal how much of the secret a guess got right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
 // This macro allows us to use it in our plugin registry!
 // register_plugin takes a group name, and a plugin name.
 register_plugin!(
@@ -102,16 +576,20 @@ register_plugin!(
 mod tests {
     use apollo_router::services::supergraph;
     use apollo_router::TestHarness;
+    use operation_analysis::operation_cost::Cost;
     use tower::BoxError;
     use tower::ServiceExt;
 
+    use super::{OperationCostResult, OPERATION_COST_CONTEXT_KEY};
+    use crate::plugins::temp_file::TempFile;
+
     #[tokio::test]
     async fn plugin_registered() {
         let config = serde_json::json!({
             "plugins": {
                 "apollosolutions.basic_operation_cost": {
                     "max_cost" : 10,
-                    "cost_map" : { "Query.hello": 10 }
+                    "cost_map" : { "Query.topProducts": 10 }
                 }
             }
         });
@@ -123,6 +601,34 @@ mod tests {
             .unwrap();
     }
 
+    #[tokio::test]
+    async fn invalid_cost_map() {
+        let config = |on_invalid_cost_map| {
+            serde_json::json!({
+                "plugins": {
+                    "apollosolutions.basic_operation_cost": {
+                        "max_cost" : 10,
+                        "cost_map" : { "Query.topProduct": 10, "Product.upc": 1 },
+                        "on_invalid_cost_map": on_invalid_cost_map
+                    }
+                }
+            })
+        };
+
+        assert!(TestHarness::builder()
+            .configuration_json(config("fail"))
+            .unwrap()
+            .build()
+            .await
+            .is_err());
+        assert!(TestHarness::builder()
+            .configuration_json(config("warn"))
+            .unwrap()
+            .build()
+            .await
+            .is_ok());
+    }
+
     #[tokio::test]
     async fn basic_test_error_response() -> Result<(), BoxError> {
         let test_harness = TestHarness::builder()
@@ -149,23 +655,67 @@ mod tests {
 
         assert!(first_response.data.is_none());
         assert_eq!(first_response.errors.len(), 1);
+        let error = first_response.errors.first().expect("qed");
+        assert_eq!(error.message, "operation cost exceeded limit");
         assert_eq!(
-            first_response.errors.first().expect("qed").message,
-            "operation cost exceeded limit"
+            error.extensions.get("code"),
+            Some(&"COST_LIMIT_EXCEEDED".into())
         );
+        assert_eq!(error.extensions.get("limit"), Some(&10.into()));
+        assert!(error.extensions.get("measured").is_some());
 
         let next = streamed_response.next_response().await;
         assert!(next.is_none());
         Ok(())
     }
 
+    #[tokio::test]
+    async fn custom_error_response() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 10,
+                    "cost_map" : { "Query.topProducts": 10 },
+                    "errors": {
+                      "limit_exceeded": {
+                        "message": "too expensive",
+                        "status_code": 429
+                      }
+                    }
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+        assert_eq!(streamed_response.response.status(), 429);
+
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        let error = first_response.errors.first().expect("qed");
+        assert_eq!(error.message, "too expensive");
+        assert_eq!(
+            error.extensions.get("code"),
+            Some(&"COST_LIMIT_EXCEEDED".into())
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn basic_test() -> Result<(), BoxError> {
         let test_harness = TestHarness::builder()
             .configuration_json(serde_json::json!({
                 "plugins": {
                   "apollosolutions.basic_operation_cost": {
-                    "max_cost" : 20,
+                    "max_cost" : 500,
                     "cost_map" : { "Query.topProducts": 2 }
                   }
                 }
@@ -189,4 +739,487 @@ mod tests {
         assert!(next.is_none());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn explain() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 500,
+                    "cost_map" : { "Query.topProducts": 2 },
+                    "explain": { "secret": "s3cr3t" }
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder()
+            .header("x-cost-explain", "s3cr3t")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.clone().oneshot(request).await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        assert!(first_response.data.is_some());
+        let explain = first_response
+            .extensions
+            .get("cost")
+            .expect("missing cost extension");
+        assert_eq!(explain.get("maxCost"), Some(&500.into()));
+        assert!(explain.get("cost").is_some());
+        assert!(explain.get("breakdown").is_some());
+
+        // wrong secret
+        let request = supergraph::Request::canned_builder()
+            .header("x-cost-explain", "true")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        assert!(first_response.extensions.get("cost").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn explain_error_response() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 10,
+                    "cost_map" : { "Query.topProducts": 10 },
+                    "explain": {}
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder()
+            .header("x-cost-explain", "true")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        assert_eq!(first_response.errors.len(), 1);
+        let explain = first_response
+            .extensions
+            .get("cost")
+            .expect("missing cost extension");
+        assert_eq!(explain.get("maxCost"), Some(&10.into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn budget_exceeded() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 500,
+                    "cost_map" : { "Query.topProducts": 2 },
+                    "budget": {
+                      "capacity": 200,
+                      "refill_per_second": 1,
+                      "client_id": { "header": "x-client-id" }
+                    }
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder()
+            .header("x-client-id", "a")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.clone().oneshot(request).await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        assert!(first_response.errors.is_empty());
+
+        let request = supergraph::Request::canned_builder()
+            .header("x-client-id", "a")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+        assert_eq!(streamed_response.response.status(), 429);
+        assert!(streamed_response
+            .response
+            .headers()
+            .contains_key("retry-after"));
+
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        let error = first_response.errors.first().expect("qed");
+        assert_eq!(
+            error.extensions.get("code"),
+            Some(&"COST_BUDGET_EXCEEDED".into())
+        );
+        assert!(error.extensions.get("remaining").is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn context() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 500,
+                    "cost_map" : { "Query.topProducts": 2 }
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let streamed_response = test_harness.oneshot(request).await?;
+        let result = streamed_response
+            .context
+            .get::<_, OperationCostResult>(OPERATION_COST_CONTEXT_KEY)?
+            .expect("missing operation cost");
+
+        assert_eq!(result.cost, Cost::new(128));
+        assert_eq!(result.max_cost, Cost::new(500));
+        assert_eq!(result.warn_at, None);
+        assert!(result
+            .breakdown
+            .iter()
+            .any(|field| field.coordinate == "Query.topProducts" && field.weight == 2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn script() -> Result<(), BoxError> {
+        let script = TempFile::new(
+            "cost_script.rhai",
+            r#"
+            fn field_cost(field) {
+                if field.coordinate == "Query.topProducts" && field.arguments.first > 1 {
+                    return 1000;
+                }
+            }
+            "#,
+        );
+
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 500,
+                    "cost_map" : { "Query.topProducts": 2 },
+                    "script": script.path()
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let first_response = test_harness
+            .oneshot(request)
+            .await?
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        let error = first_response.errors.first().expect("qed");
+        assert_eq!(error.extensions.get("measured"), Some(&1000.into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cost_map_file() -> Result<(), BoxError> {
+        let file = TempFile::new("cost_map.json", r#"{ "Query.topProducts": 10 }"#);
+
+        let measured = |cost_map| {
+            let path = file.path().to_path_buf();
+            async move {
+                let test_harness = TestHarness::builder()
+                    .configuration_json(serde_json::json!({
+                        "plugins": {
+                          "apollosolutions.basic_operation_cost": {
+                            "max_cost" : 100,
+                            "cost_map" : cost_map,
+                            "cost_map_file": path
+                          }
+                        }
+                    }))
+                    .unwrap()
+                    .build()
+                    .await
+                    .unwrap();
+
+                let request = supergraph::Request::canned_builder().build().unwrap();
+                let first_response = test_harness
+                    .oneshot(request)
+                    .await
+                    .unwrap()
+                    .next_response()
+                    .await
+                    .expect("couldn't get primary response");
+                let error = first_response.errors.first().expect("qed");
+                error.extensions.get("measured").cloned()
+            }
+        };
+
+        let from_file = measured(serde_json::json!({})).await;
+        // the inline entry takes precedence
+        let from_inline = measured(serde_json::json!({ "Query.topProducts": 2 })).await;
+
+        assert_eq!(from_file, Some(136.into()));
+        assert_eq!(from_inline, Some(128.into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn overlays() -> Result<(), BoxError> {
+        let overlay = TempFile::new(
+            "cost_overlay.graphql",
+            "extend type Query { topProducts(first: Int = 5): [Product] @cost(weight: 10) }",
+        );
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 100,
+                    "overlays": [overlay.path()]
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await;
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let first_response = test_harness
+            .unwrap()
+            .oneshot(request)
+            .await?
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        let error = first_response.errors.first().expect("qed");
+        assert_eq!(error.extensions.get("measured"), Some(&136.into()));
+
+        let overlay = TempFile::new(
+            "cost_overlay.graphql",
+            "extend type Product { nope: String @cost(weight: 1) }",
+        );
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 100,
+                    "overlays": [overlay.path()]
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await;
+        assert!(test_harness.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_operation() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 100,
+                    "cost_map" : { "Query.topProducts": 2 }
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder()
+            .query("{ topProducts { name }")
+            .build()
+            .unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+        assert_eq!(streamed_response.response.status(), 400);
+
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        let error = first_response.errors.first().expect("qed");
+        assert_eq!(
+            error.extensions.get("code"),
+            Some(&"COST_INVALID_OPERATION".into())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn measure_mode() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 10,
+                    "mode": "measure",
+                    "cost_map" : { "Query.topProducts": 10 }
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+        assert_eq!(
+            streamed_response
+                .context
+                .get::<_, Vec<String>>("apollosolutions.would_reject")?,
+            Some(vec!["COST_LIMIT_EXCEEDED".to_string()])
+        );
+
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        assert!(first_response.data.is_some());
+        assert!(first_response.errors.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn warn_at() -> Result<(), BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 500,
+                    "warn_at": 100,
+                    "cost_map" : { "Query.topProducts": 2 }
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+        let mut streamed_response = test_harness.oneshot(request).await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        assert!(first_response.data.is_some());
+        assert!(first_response.errors.is_empty());
+        let warnings = first_response
+            .extensions
+            .get("warnings")
+            .and_then(|warnings| warnings.as_array())
+            .expect("missing warnings extension");
+        assert_eq!(warnings.len(), 1);
+        let extensions = warnings[0].get("extensions").expect("qed");
+        assert_eq!(
+            extensions.get("code"),
+            Some(&"COST_APPROACHING_LIMIT".into())
+        );
+        assert_eq!(extensions.get("measured"), Some(&128.into()));
+        assert_eq!(extensions.get("threshold"), Some(&100.into()));
+        Ok(())
+    }
+
+    async fn unreachable_store_test_harness(
+        on_store_error: &str,
+    ) -> Result<supergraph::Response, BoxError> {
+        let test_harness = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "plugins": {
+                  "apollosolutions.basic_operation_cost": {
+                    "max_cost" : 500,
+                    "cost_map" : { "Query.topProducts": 2 },
+                    "budget": {
+                      "capacity": 200,
+                      "refill_per_second": 1,
+                      "client_id": { "header": "x-client-id" },
+                      "store": { "redis": { "url": "redis://127.0.0.1:1" } },
+                      "on_store_error": on_store_error
+                    }
+                  }
+                }
+            }))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::canned_builder()
+            .header("x-client-id", "a")
+            .build()
+            .unwrap();
+        test_harness.oneshot(request).await
+    }
+
+    #[tokio::test]
+    async fn budget_store_unavailable_allow() -> Result<(), BoxError> {
+        let mut streamed_response = unreachable_store_test_harness("allow").await?;
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+
+        assert!(first_response.data.is_some());
+        assert!(first_response.errors.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn budget_store_unavailable_reject() -> Result<(), BoxError> {
+        let mut streamed_response = unreachable_store_test_harness("reject").await?;
+        assert_eq!(streamed_response.response.status(), 503);
+
+        let first_response = streamed_response
+            .next_response()
+            .await
+            .expect("couldn't get primary response");
+        let error = first_response.errors.first().expect("qed");
+        assert_eq!(
+            error.extensions.get("code"),
+            Some(&"COST_BUDGET_UNAVAILABLE".into())
+        );
+        Ok(())
+    }
 }
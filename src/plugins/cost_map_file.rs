@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use std::time::Duration;
+
+use operation_analysis::cost_map_file;
+use tokio::time::MissedTickBehavior;
+use tower::BoxError;
+
+/// A `cost_map_file`, and what was last read from it.
+#[derive(Debug)]
+pub(crate) struct CostMapFile {
+    path: PathBuf,
+    /// The last cost map read, or the error reading it.
+    last: Result<HashMap<String, usize>, String>,
+}
+
+impl CostMapFile {
+    pub(crate) fn read(path: &Path) -> Result<(Self, HashMap<String, usize>), BoxError> {
+        let cost_map = cost_map_file::read(path)?;
+
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                last: Ok(cost_map.clone()),
+            },
+            cost_map,
+        ))
+    }
+
+    /// Reads the file again. Returns the new cost map if it changed since the
+    /// last poll. Versions that can't be read or parsed are logged, once,
+    /// and skipped.
+    async fn poll(&mut self) -> Option<HashMap<String, usize>> {
+        let path = self.path.clone();
+        let result = tokio::task::spawn_blocking(move || cost_map_file::read(&path))
+            .await
+            .map_err(|err| err.to_string())
+            .and_then(|result| result.map_err(|err| err.to_string()));
+
+        match result {
+            Ok(cost_map) if self.last.as_ref() == Ok(&cost_map) => None,
+            Ok(cost_map) => {
+                self.last = Ok(cost_map.clone());
+                Some(cost_map)
+            }
+            Err(err) => {
+                if self.last.as_ref().err() != Some(&err) {
+                    tracing::error!(
+                        path = %self.path.display(),
+                        "keeping the previous cost map: {}",
+                        err
+                    );
+                }
+                self.last = Err(err);
+                None
+            }
+        }
+    }
+
+    /// Checks the file for changes every `interval`, and calls `apply` with
+    /// `target` and each new cost map. Stops once `target` is dropped.
+    pub(crate) fn watch<T: Send + Sync + 'static>(
+        mut self,
+        interval: Duration,
+        target: Weak<T>,
+        apply: impl Fn(&T, HashMap<String, usize>) + Send + 'static,
+    ) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // the first tick completes immediately
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                let target = match target.upgrade() {
+                    Some(target) => target,
+                    None => break,
+                };
+
+                if let Some(cost_map) = self.poll().await {
+                    apply(&target, cost_map);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CostMapFile;
+    use crate::plugins::temp_file::TempFile;
+
+    #[tokio::test]
+    async fn poll() {
+        let temp_file = TempFile::new("cost_map.yaml", "Query.topProducts: 5");
+
+        let (mut file, cost_map) = CostMapFile::read(temp_file.path()).unwrap();
+        assert_eq!(cost_map["Query.topProducts"], 5);
+        assert_eq!(file.poll().await, None);
+
+        temp_file.write("Query.topProducts: 7");
+        assert_eq!(file.poll().await.unwrap()["Query.topProducts"], 7);
+        assert_eq!(file.poll().await, None);
+
+        // formatting changes don't count
+        temp_file.write("Query.topProducts:  7\n");
+        assert_eq!(file.poll().await, None);
+
+        // versions that can't be parsed are skipped
+        temp_file.write("Query.topProducts: [");
+        assert_eq!(file.poll().await, None);
+        temp_file.write("Query.topProducts: 9");
+        assert_eq!(file.poll().await.unwrap()["Query.topProducts"], 9);
+
+        temp_file.remove();
+        assert_eq!(file.poll().await, None);
+    }
+}
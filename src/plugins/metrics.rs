@@ -0,0 +1,281 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use apollo_router::services::supergraph;
+use opentelemetry::metrics::{Counter, Histogram};
+use opentelemetry::{global, KeyValue};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+
+/// Meter the plugins' instruments are created with. The router exports it
+/// with its own metrics, through whichever exporters telemetry configures.
+const METER_NAME: &str = "apollosolutions";
+
+/// How a plugin dealt with an operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Outcome {
+    Accepted,
+    /// Accepted with a warning.
+    Warned,
+    Rejected,
+    /// Accepted in measure mode, but would have been rejected.
+    WouldReject,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Accepted => "accepted",
+            Outcome::Warned => "warned",
+            Outcome::Rejected => "rejected",
+            Outcome::WouldReject => "would_reject",
+        }
+    }
+}
+
+/// Request attributes, as named in [`MetricsConf::allowed`].
+const ATTRIBUTES: [&str; 3] = ["operation.name", "client.name", "client.version"];
+
+/// Longest request attribute value recorded as is.
+const MAX_ATTRIBUTE_LENGTH: usize = 64;
+
+/// Value recorded in place of request attributes over the limits.
+const OTHER: &str = "other";
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct MetricsConf {
+    /// Values of `operation.name`, `client.name` or `client.version` that
+    /// are always recorded, whatever other values have been seen.
+    pub(crate) allowed: HashMap<String, Vec<String>>,
+    /// Distinct values of each attribute recorded besides the allowed ones,
+    /// first come first served. 0 records only the allowed values.
+    pub(crate) max_values: usize,
+}
+
+impl Default for MetricsConf {
+    fn default() -> Self {
+        Self {
+            allowed: HashMap::new(),
+            max_values: 100,
+        }
+    }
+}
+
+/// Attributes shared by the plugins' metrics: the operation name and the
+/// client name and version sent in the `apollographql-client-*` headers.
+///
+/// The values come from clients, so they're bounded to keep the number of
+/// time series in check. Allowed values are always recorded. Other values
+/// that are long, contain anything but letters, digits, `_`, `-` and `.`, or
+/// come after the first `max_values` distinct ones are recorded as `other`.
+#[derive(Clone, Debug)]
+pub(crate) struct RequestAttributes {
+    allowed: Arc<HashMap<String, HashSet<String>>>,
+    max_values: usize,
+    seen: Arc<Mutex<HashMap<&'static str, HashSet<String>>>>,
+}
+
+impl RequestAttributes {
+    pub(crate) fn new(conf: &MetricsConf) -> Result<Self, BoxError> {
+        if let Some(key) = conf
+            .allowed
+            .keys()
+            .find(|key| !ATTRIBUTES.contains(&key.as_str()))
+        {
+            return Err(format!(
+                "unknown request attribute {}, expected one of {}",
+                key,
+                ATTRIBUTES.join(", ")
+            )
+            .into());
+        }
+
+        Ok(Self {
+            allowed: Arc::new(
+                conf.allowed
+                    .iter()
+                    .map(|(key, values)| (key.clone(), values.iter().cloned().collect()))
+                    .collect(),
+            ),
+            max_values: conf.max_values,
+            seen: Default::default(),
+        })
+    }
+
+    pub(crate) fn of(&self, req: &supergraph::Request) -> Vec<KeyValue> {
+        let header = |name: &str| {
+            req.supergraph_request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+        };
+
+        vec![
+            self.bounded(
+                "operation.name",
+                req.supergraph_request
+                    .body()
+                    .operation_name
+                    .as_deref()
+                    .unwrap_or_default(),
+            ),
+            self.bounded("client.name", header("apollographql-client-name")),
+            self.bounded("client.version", header("apollographql-client-version")),
+        ]
+    }
+
+    fn bounded(&self, key: &'static str, value: &str) -> KeyValue {
+        if self
+            .allowed
+            .get(key)
+            .map_or(false, |values| values.contains(value))
+        {
+            return KeyValue::new(key, value.to_string());
+        }
+
+        let valid = value.len() <= MAX_ATTRIBUTE_LENGTH
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        if !valid {
+            return KeyValue::new(key, OTHER);
+        }
+
+        let mut seen = self.seen.lock().expect("attributes lock poisoned");
+        let values = seen.entry(key).or_default();
+        if values.contains(value) || values.len() < self.max_values {
+            values.insert(value.to_string());
+            KeyValue::new(key, value.to_string())
+        } else {
+            KeyValue::new(key, OTHER)
+        }
+    }
+}
+
+/// Histogram of a measured value, e.g. cost or depth, by outcome.
+#[derive(Clone, Debug)]
+pub(crate) struct OperationHistogram(Histogram<u64>);
+
+impl OperationHistogram {
+    pub(crate) fn new(name: &'static str, description: &'static str) -> Self {
+        Self(
+            global::meter(METER_NAME)
+                .u64_histogram(name)
+                .with_description(description)
+                .init(),
+        )
+    }
+
+    pub(crate) fn record(&self, value: usize, attributes: &[KeyValue], outcome: Outcome) {
+        self.0
+            .record(value as u64, &with_outcome(attributes, outcome));
+    }
+}
+
+/// Counter of rejections by reason, i.e. error code, and outcome.
+#[derive(Clone, Debug)]
+pub(crate) struct RejectionCounter(Counter<u64>);
+
+impl RejectionCounter {
+    pub(crate) fn new() -> Self {
+        Self(
+            global::meter(METER_NAME)
+                .u64_counter("apollosolutions.operation.rejections")
+                .with_description("Operations rejected by the cost and depth plugins")
+                .init(),
+        )
+    }
+
+    pub(crate) fn add(&self, reason: &'static str, attributes: &[KeyValue], outcome: Outcome) {
+        let mut attributes = with_outcome(attributes, outcome);
+        attributes.push(KeyValue::new("reason", reason));
+        self.0.add(1, &attributes);
+    }
+}
+
+fn with_outcome(attributes: &[KeyValue], outcome: Outcome) -> Vec<KeyValue> {
+    let mut attributes = attributes.to_vec();
+    attributes.push(KeyValue::new("outcome", outcome.as_str()));
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use apollo_router::services::supergraph;
+    use opentelemetry::KeyValue;
+
+    use super::{MetricsConf, RequestAttributes};
+
+    fn request(client_name: &str) -> supergraph::Request {
+        supergraph::Request::canned_builder()
+            .header("apollographql-client-name", client_name)
+            .header("apollographql-client-version", "1.2.3")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn attributes() {
+        let attributes = RequestAttributes::new(&MetricsConf::default())
+            .unwrap()
+            .of(&request("web"));
+        assert!(attributes.contains(&KeyValue::new("client.name", "web")));
+        assert!(attributes.contains(&KeyValue::new("client.version", "1.2.3")));
+    }
+
+    #[test]
+    fn bounded_cardinality() {
+        let request_attributes = RequestAttributes::new(&MetricsConf::default()).unwrap();
+
+        let attributes = request_attributes.of(&request(&"a".repeat(65)));
+        assert!(attributes.contains(&KeyValue::new("client.name", "other")));
+        let attributes = request_attributes.of(&request("web app"));
+        assert!(attributes.contains(&KeyValue::new("client.name", "other")));
+
+        for i in 0..MetricsConf::default().max_values {
+            let attributes = request_attributes.of(&request(&format!("client-{}", i)));
+            assert!(attributes.contains(&KeyValue::new("client.name", format!("client-{}", i))));
+        }
+        let attributes = request_attributes.of(&request("one-too-many"));
+        assert!(attributes.contains(&KeyValue::new("client.name", "other")));
+        // values already seen are still recorded
+        let attributes = request_attributes.of(&request("client-0"));
+        assert!(attributes.contains(&KeyValue::new("client.name", "client-0")));
+    }
+
+    #[test]
+    fn allowed_values() {
+        let conf: MetricsConf = serde_json::from_value(serde_json::json!({
+            "allowed": { "client.name": ["web app", "mobile"] },
+            "max_values": 2,
+        }))
+        .unwrap();
+        let request_attributes = RequestAttributes::new(&conf).unwrap();
+
+        // junk values take the first come slots
+        for name in ["junk-1", "junk-2", "junk-3"] {
+            request_attributes.of(&request(name));
+        }
+        let attributes = request_attributes.of(&request("junk-3"));
+        assert!(attributes.contains(&KeyValue::new("client.name", "other")));
+
+        // but can't lock out allowed values, which are recorded as is
+        let attributes = request_attributes.of(&request("mobile"));
+        assert!(attributes.contains(&KeyValue::new("client.name", "mobile")));
+        let attributes = request_attributes.of(&request("web app"));
+        assert!(attributes.contains(&KeyValue::new("client.name", "web app")));
+        // other attributes still have their slots
+        assert!(attributes.contains(&KeyValue::new("client.version", "1.2.3")));
+    }
+
+    #[test]
+    fn unknown_allowed_attribute() {
+        let mut conf = MetricsConf::default();
+        conf.allowed
+            .insert("client.id".to_string(), vec!["web".to_string()]);
+        assert!(RequestAttributes::new(&conf).is_err());
+    }
+}
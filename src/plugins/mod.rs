@@ -1,2 +1,38 @@
 mod basic_depth_limit;
 mod basic_operation_cost;
+mod cost_map_file;
+mod metrics;
+mod rejection;
+#[cfg(test)]
+mod temp_file;
+mod warning;
+
+use apollo_router::services::supergraph;
+use futures::StreamExt;
+
+/// Copies the value under `context_key`, if any, into the `name` extension of
+/// the first response. This lets a request checkpoint add extensions to
+/// responses, whether or not it broke the request.
+fn extension_from_context(
+    res: supergraph::Response,
+    context_key: &str,
+    name: &'static str,
+) -> supergraph::Response {
+    match res.context.get::<_, serde_json::Value>(context_key) {
+        Ok(Some(value)) => {
+            let value = serde_json_bytes::Value::from(value);
+            res.map(move |stream| {
+                stream
+                    .enumerate()
+                    .map(move |(i, mut response)| {
+                        if i == 0 {
+                            response.extensions.insert(name, value.clone());
+                        }
+                        response
+                    })
+                    .boxed()
+            })
+        }
+        _ => res,
+    }
+}
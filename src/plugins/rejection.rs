@@ -0,0 +1,204 @@
+use apollo_router::graphql::Error;
+use apollo_router::services::supergraph;
+use apollo_router::Context;
+use http::StatusCode;
+use opentelemetry::KeyValue;
+use operation_analysis::error::OperationError;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json_bytes::Value;
+use tower::BoxError;
+
+use super::metrics::{Outcome, RejectionCounter};
+
+/// Context key listing the codes of the rejections a request would have
+/// received from plugins in measure mode.
+const WOULD_REJECT_CONTEXT_KEY: &str = "apollosolutions.would_reject";
+
+/// Whether a plugin rejects requests or only reports what it would reject.
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Mode {
+    /// Log, count and tag requests that would be rejected, but let them
+    /// through.
+    Measure,
+    /// Reject requests.
+    Enforce,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Enforce
+    }
+}
+
+/// What a plugin does with requests whose operation it can't analyse.
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OnError {
+    /// Reject the request, with a 400 if the operation is at fault (e.g. a
+    /// syntax error) and a 500 otherwise.
+    Reject,
+    /// Log the error and let the request through.
+    Allow,
+}
+
+impl Default for OnError {
+    fn default() -> Self {
+        OnError::Reject
+    }
+}
+
+/// Overrides for the message and HTTP status of an error response.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub(crate) struct RejectionConf {
+    /// Error message. Defaults to the plugin's built-in message.
+    message: Option<String>,
+    /// HTTP status code. Defaults to the plugin's built-in status.
+    status_code: Option<u16>,
+}
+
+/// An error response with a stable `extensions.code`, so clients can tell
+/// the plugins' errors apart from others.
+#[derive(Clone, Debug)]
+pub(crate) struct Rejection {
+    code: &'static str,
+    message: String,
+    status_code: StatusCode,
+    mode: Mode,
+    counter: RejectionCounter,
+}
+
+impl Rejection {
+    pub(crate) fn new(
+        code: &'static str,
+        default_message: &str,
+        default_status_code: StatusCode,
+        conf: &RejectionConf,
+        mode: Mode,
+    ) -> Result<Self, BoxError> {
+        let status_code = match conf.status_code {
+            Some(status_code) => StatusCode::from_u16(status_code)?,
+            None => default_status_code,
+        };
+
+        Ok(Self {
+            code,
+            message: conf
+                .message
+                .clone()
+                .unwrap_or_else(|| default_message.to_string()),
+            status_code,
+            mode,
+            counter: RejectionCounter::new(),
+        })
+    }
+
+    /// Returns the error response to break the request with, adding
+    /// `extensions` (e.g. the measured value and the limit it exceeded) next
+    /// to the code.
+    ///
+    /// In measure mode, logs and tags the request instead, and returns
+    /// `None` so it can go on. Either way the rejection is counted, with
+    /// the request's metric `attributes`.
+    pub(crate) fn reject(
+        &self,
+        context: &Context,
+        attributes: &[KeyValue],
+        extensions: &[(&'static str, usize)],
+    ) -> Result<Option<supergraph::Response>, BoxError> {
+        match self.mode {
+            Mode::Enforce => {
+                self.counter.add(self.code, attributes, Outcome::Rejected);
+                // becomes an event on the plugin's span
+                tracing::info!(code = self.code, ?extensions, "request rejected");
+                self.response(context.clone(), extensions).map(Some)
+            }
+            Mode::Measure => {
+                self.counter
+                    .add(self.code, attributes, Outcome::WouldReject);
+                tracing::info!(
+                    code = self.code,
+                    ?extensions,
+                    "request would have been rejected"
+                );
+
+                let mut codes = context
+                    .get::<_, Vec<String>>(WOULD_REJECT_CONTEXT_KEY)?
+                    .unwrap_or_default();
+                codes.push(self.code.to_string());
+                context.insert(WOULD_REJECT_CONTEXT_KEY, codes)?;
+
+                Ok(None)
+            }
+        }
+    }
+
+    fn response(
+        &self,
+        context: Context,
+        extensions: &[(&'static str, usize)],
+    ) -> Result<supergraph::Response, BoxError> {
+        let mut error = Error::builder()
+            .message(self.message.clone())
+            .extension("code", self.code);
+        for (key, value) in extensions {
+            error = error.extension(*key, Value::Number((*value).into()));
+        }
+
+        supergraph::Response::builder()
+            .error(error.build())
+            .status_code(self.status_code)
+            .context(context)
+            .build()
+    }
+}
+
+/// How a plugin answers requests whose operation it can't analyse.
+#[derive(Clone, Debug)]
+pub(crate) struct AnalysisErrors {
+    on_error: OnError,
+    /// For problems with the operation itself, see [`OperationError`].
+    invalid_operation: Rejection,
+    /// For everything else.
+    calculation_failed: Rejection,
+}
+
+impl AnalysisErrors {
+    pub(crate) fn new(
+        on_error: OnError,
+        invalid_operation: Rejection,
+        calculation_failed: Rejection,
+    ) -> Self {
+        Self {
+            on_error,
+            invalid_operation,
+            calculation_failed,
+        }
+    }
+
+    /// Returns the error response to break the request with, if `on_error`
+    /// is `reject`. See [`Rejection::reject`].
+    pub(crate) fn reject(
+        &self,
+        err: &anyhow::Error,
+        context: &Context,
+        attributes: &[KeyValue],
+    ) -> Result<Option<supergraph::Response>, BoxError> {
+        match self.on_error {
+            OnError::Reject if err.downcast_ref::<OperationError>().is_some() => {
+                tracing::info!("invalid operation: {}", err);
+                self.invalid_operation.reject(context, attributes, &[])
+            }
+            OnError::Reject => {
+                tracing::error!("could not analyse operation: {}", err);
+                self.calculation_failed.reject(context, attributes, &[])
+            }
+            OnError::Allow => {
+                tracing::warn!("could not analyse operation: {}", err);
+                Ok(None)
+            }
+        }
+    }
+}
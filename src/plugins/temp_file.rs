@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A file in the temporary directory, for tests. It is removed when dropped,
+/// so a failing test doesn't leave it behind.
+#[derive(Debug)]
+pub(crate) struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Writes `contents` to a new file whose name ends with `name`, e.g.
+    /// `cost_map.yaml`. Names are unique across the tests of a process.
+    pub(crate) fn new(name: &str, contents: &str) -> Self {
+        let file = Self {
+            path: std::env::temp_dir().join(format!(
+                "{}_{}_{}",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                name
+            )),
+        };
+        file.write(contents);
+        file
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn write(&self, contents: &str) {
+        std::fs::write(&self.path, contents).expect("couldn't write the temporary file");
+    }
+
+    pub(crate) fn remove(&self) {
+        std::fs::remove_file(&self.path).expect("couldn't remove the temporary file");
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,56 @@
+use apollo_router::services::supergraph;
+use apollo_router::Context;
+use serde_json::{json, Map, Value};
+use tower::BoxError;
+
+/// Context key under which warnings are collected until the response.
+const WARNINGS_CONTEXT_KEY: &str = "apollosolutions.warnings";
+
+/// A warning added to the response's `extensions.warnings` for operations
+/// that execute but are close to a limit. Warnings have the shape of GraphQL
+/// errors, with a stable `extensions.code`.
+#[derive(Clone, Debug)]
+pub(crate) struct Warning {
+    code: &'static str,
+    message: &'static str,
+}
+
+impl Warning {
+    pub(crate) fn new(code: &'static str, message: &'static str) -> Self {
+        Self { code, message }
+    }
+
+    /// Logs the warning and queues it for the response, with `extensions`
+    /// (e.g. the measured value and the threshold it exceeded) next to the
+    /// code.
+    pub(crate) fn warn(
+        &self,
+        context: &Context,
+        extensions: &[(&'static str, usize)],
+    ) -> Result<(), BoxError> {
+        tracing::info!(code = self.code, ?extensions, "{}", self.message);
+
+        let mut error_extensions = Map::new();
+        error_extensions.insert("code".to_string(), self.code.into());
+        for (key, value) in extensions {
+            error_extensions.insert(key.to_string(), (*value).into());
+        }
+
+        let mut warnings = context
+            .get::<_, Vec<Value>>(WARNINGS_CONTEXT_KEY)?
+            .unwrap_or_default();
+        warnings.push(json!({
+            "message": self.message,
+            "extensions": error_extensions,
+        }));
+        context.insert(WARNINGS_CONTEXT_KEY, warnings)?;
+
+        Ok(())
+    }
+}
+
+/// Adds the warnings queued for the request to the response. Both plugins
+/// call this, which is fine since each copies the full list.
+pub(crate) fn with_warnings(res: supergraph::Response) -> supergraph::Response {
+    super::extension_from_context(res, WARNINGS_CONTEXT_KEY, "warnings")
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use operation_analysis::cost_map_file;
+use operation_analysis::operation_cost::ListSizeConf;
+use serde::Deserialize;
+
+const COST_PLUGIN: &str = "apollosolutions.basic_operation_cost";
+const DEPTH_PLUGIN: &str = "apollosolutions.basic_depth_limit";
+
+/// The parts of a router.yaml the plugins' analysis depends on.
+#[derive(Debug, Default)]
+pub struct RouterConfig {
+    pub cost: Option<CostConf>,
+    pub depth: Option<DepthConf>,
+}
+
+/// Settings of the `basic_operation_cost` plugin that affect the cost.
+#[derive(Debug, Default, Deserialize)]
+pub struct CostConf {
+    #[serde(default)]
+    pub cost_map: HashMap<String, usize>,
+    pub cost_map_file: Option<PathBuf>,
+    #[serde(default)]
+    pub overlays: Vec<PathBuf>,
+    pub max_cost: Option<usize>,
+    #[serde(default)]
+    pub list_size: ListSizeConf,
+    pub script: Option<PathBuf>,
+}
+
+/// Settings of the `basic_depth_limit` plugin.
+#[derive(Debug, Deserialize)]
+pub struct DepthConf {
+    pub limit: usize,
+}
+
+impl RouterConfig {
+    pub fn read(path: &Path) -> Result<Self> {
+        let yaml = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        let mut config =
+            Self::parse(&yaml).with_context(|| format!("could not parse {}", path.display()))?;
+
+        // entries in the inline cost_map take precedence, as in the plugin
+        if let Some(cost) = &mut config.cost {
+            if let Some(path) = &cost.cost_map_file {
+                let mut cost_map = cost_map_file::read(path)?;
+                cost_map.extend(cost.cost_map.drain());
+                cost.cost_map = cost_map;
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// `max_cost` of the cost plugin, if configured.
+    pub fn max_cost(&self) -> Option<usize> {
+        self.cost.as_ref().and_then(|cost| cost.max_cost)
+    }
+
+    /// `limit` of the depth plugin, if configured.
+    pub fn depth_limit(&self) -> Option<usize> {
+        self.depth.as_ref().map(|depth| depth.limit)
+    }
+
+    pub fn parse(yaml: &str) -> Result<Self> {
+        let config: serde_yaml::Value = serde_yaml::from_str(yaml)?;
+        let plugin = |name: &str| config.get("plugins").and_then(|plugins| plugins.get(name));
+
+        Ok(Self {
+            cost: plugin(COST_PLUGIN)
+                .map(|conf| serde_yaml::from_value(conf.clone()))
+                .transpose()
+                .with_context(|| format!("invalid {} configuration", COST_PLUGIN))?,
+            depth: plugin(DEPTH_PLUGIN)
+                .map(|conf| serde_yaml::from_value(conf.clone()))
+                .transpose()
+                .with_context(|| format!("invalid {} configuration", DEPTH_PLUGIN))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RouterConfig;
+
+    #[test]
+    fn parse() {
+        let config = RouterConfig::parse(
+            "
+plugins:
+  apollosolutions.basic_depth_limit:
+    limit: 14
+  apollosolutions.basic_operation_cost:
+    cost_map:
+      Query.me: 10
+    max_cost: 100
+    list_size:
+      assumed_size: 5
+    explain: {}
+",
+        )
+        .unwrap();
+
+        let cost = config.cost.unwrap();
+        assert_eq!(cost.cost_map.get("Query.me"), Some(&10));
+        assert_eq!(cost.max_cost, Some(100));
+        assert_eq!(cost.list_size.assumed_size, 5);
+        assert_eq!(config.depth.unwrap().limit, 14);
+
+        let config = RouterConfig::parse("supergraph:\n  listen: 127.0.0.1:4000\n").unwrap();
+        assert!(config.cost.is_none());
+        assert!(config.depth.is_none());
+    }
+}
@@ -0,0 +1,239 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use operation_analysis::cost_script::CostScript;
+use operation_analysis::operation_cost::{operation_cost, Cost, FieldCost};
+use operation_analysis::operation_depth::operation_depth;
+use operation_analysis::schema::Schema;
+use serde::Serialize;
+use serde_json::{Map, Value as JsonValue};
+
+use crate::config::RouterConfig;
+
+/// Calculate the cost and depth of an operation, as the router plugins would
+#[derive(Args, Debug)]
+pub struct CostArgs {
+    /// Supergraph schema
+    #[clap(long)]
+    supergraph: PathBuf,
+    /// Router configuration to read the plugins' settings from
+    #[clap(long, default_value = "router.yaml")]
+    config: PathBuf,
+    /// File containing the operation
+    #[clap(long)]
+    operation: PathBuf,
+    /// Operation to analyse, if the file has several
+    #[clap(long)]
+    operation_name: Option<String>,
+    /// JSON file containing the operation's variables
+    #[clap(long)]
+    variables: Option<PathBuf>,
+    #[clap(long, value_enum, default_value = "human")]
+    format: Format,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub cost: Cost,
+    pub max_cost: Option<usize>,
+    pub depth: usize,
+    pub depth_limit: Option<usize>,
+    /// Whether the operation is within both limits.
+    pub pass: bool,
+    pub breakdown: Vec<FieldCost>,
+    /// Cost contributed by each coordinate, most expensive first.
+    #[serde(skip)]
+    pub coordinates: Vec<(String, Cost)>,
+}
+
+impl CostArgs {
+    pub fn execute(&self) -> Result<()> {
+        let config = RouterConfig::read(&self.config)?;
+        let supergraph = std::fs::read_to_string(&self.supergraph)
+            .with_context(|| format!("could not read {}", self.supergraph.display()))?;
+        let operation = std::fs::read_to_string(&self.operation)
+            .with_context(|| format!("could not read {}", self.operation.display()))?;
+        let variables = match &self.variables {
+            Some(path) => {
+                let variables = std::fs::read_to_string(path)
+                    .with_context(|| format!("could not read {}", path.display()))?;
+                serde_json::from_str(&variables)
+                    .with_context(|| format!("could not parse {}", path.display()))?
+            }
+            None => Map::new(),
+        };
+
+        let analyzer = Analyzer::new(&config, &supergraph)?;
+        let report = analyzer.analyze(&operation, self.operation_name.as_deref(), &variables)?;
+
+        match self.format {
+            Format::Human => print_human(&report),
+            Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        }
+
+        if !report.pass {
+            bail!("operation exceeds the configured limits");
+        }
+        Ok(())
+    }
+}
+
+/// Analyses operations against a supergraph and router configuration.
+pub struct Analyzer<'a> {
+    config: &'a RouterConfig,
+    schema: Schema,
+    script: Option<CostScript>,
+}
+
+impl<'a> Analyzer<'a> {
+    pub fn new(config: &'a RouterConfig, supergraph: &str) -> Result<Self> {
+        let script = match config.cost.as_ref().and_then(|cost| cost.script.as_ref()) {
+            Some(path) => Some(CostScript::new(path)?),
+            None => None,
+        };
+
+        let mut schema = Schema::new(supergraph);
+        for path in config.cost.iter().flat_map(|cost| &cost.overlays) {
+            let overlay = std::fs::read_to_string(path)
+                .with_context(|| format!("could not read {}", path.display()))?;
+            schema
+                .apply_overlay(&overlay)
+                .with_context(|| format!("invalid overlay {}", path.display()))?;
+        }
+
+        Ok(Self {
+            config,
+            schema,
+            script,
+        })
+    }
+
+    pub fn analyze(
+        &self,
+        operation: &str,
+        operation_name: Option<&str>,
+        variables: &Map<String, JsonValue>,
+    ) -> Result<Report> {
+        let cost_conf = self.config.cost.as_ref();
+        let cost = operation_cost(
+            &self.schema,
+            operation,
+            operation_name,
+            variables,
+            &cost_conf
+                .map(|cost| cost.cost_map.clone())
+                .unwrap_or_default(),
+            &cost_conf
+                .map(|cost| cost.list_size.clone())
+                .unwrap_or_default(),
+            self.script.as_ref(),
+        )?;
+        let depth = operation_depth(operation, operation_name, variables)?.depth;
+
+        let max_cost = self.config.max_cost();
+        let depth_limit = self.config.depth_limit();
+        let pass = max_cost.map_or(true, |max_cost| cost.cost.get() <= max_cost)
+            && depth_limit.map_or(true, |limit| depth <= limit);
+
+        Ok(Report {
+            cost: cost.cost,
+            max_cost,
+            depth,
+            depth_limit,
+            pass,
+            coordinates: cost.top_coordinates(usize::MAX),
+            breakdown: cost.breakdown,
+        })
+    }
+}
+
+fn print_human(report: &Report) {
+    let limit = |limit: Option<usize>| match limit {
+        Some(limit) => format!(" (limit {})", limit),
+        None => String::new(),
+    };
+
+    println!("cost:  {}{}", report.cost, limit(report.max_cost));
+    println!("depth: {}{}", report.depth, limit(report.depth_limit));
+    println!();
+
+    let coordinate_width = report
+        .breakdown
+        .iter()
+        .map(|field| field.coordinate.len())
+        .max()
+        .unwrap_or(0)
+        .max("coordinate".len());
+    println!(
+        "{:coordinate_width$}  {:>6}  {:>8}  {:>8}",
+        "coordinate",
+        "weight",
+        "count",
+        "total",
+        coordinate_width = coordinate_width,
+    );
+    for field in &report.breakdown {
+        println!(
+            "{:coordinate_width$}  {:>6}  {:>8}  {:>8}",
+            field.coordinate,
+            field.weight,
+            field.count,
+            field.total.to_string(),
+            coordinate_width = coordinate_width,
+        );
+    }
+    println!();
+
+    println!("{}", if report.pass { "PASS" } else { "FAIL" });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Map;
+
+    use super::Analyzer;
+    use crate::config::RouterConfig;
+
+    #[test]
+    fn analyze() {
+        let config = RouterConfig::parse(
+            "
+plugins:
+  apollosolutions.basic_depth_limit:
+    limit: 2
+  apollosolutions.basic_operation_cost:
+    cost_map:
+      Query.a: 3
+    max_cost: 100
+",
+        )
+        .unwrap();
+        let analyzer = Analyzer::new(
+            &config,
+            "type Query { a(first: Int): [A] } type A { b: B } type B { c: String }",
+        )
+        .unwrap();
+
+        let report = analyzer
+            .analyze("{ a(first: 2) { b { c } } }", None, &Map::new())
+            .unwrap();
+        assert_eq!(report.cost.get(), 7);
+        assert_eq!(report.depth, 3);
+        assert_eq!(report.breakdown.len(), 3);
+        assert!(!report.pass);
+
+        let report = analyzer
+            .analyze("{ a(first: 2) { __typename } }", None, &Map::new())
+            .unwrap();
+        assert_eq!(report.cost.get(), 3);
+        assert!(report.pass);
+    }
+}
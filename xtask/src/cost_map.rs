@@ -0,0 +1,310 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use apollo_compiler::values::{Directive, FieldDefinition};
+use apollo_compiler::ApolloCompiler;
+use clap::{Args, Parser};
+use operation_analysis::compiler_ext::{TypeAdditions, ValueAdditions};
+use serde::Serialize;
+
+use crate::config::RouterConfig;
+
+/// Root operation types, whose fields are entry points rather than fields
+/// of an entity.
+const ROOT_TYPES: [&str; 3] = ["Query", "Mutation", "Subscription"];
+
+/// Generate a starter cost_map covering every field of the supergraph
+#[derive(Args, Debug)]
+pub struct CostMapArgs {
+    /// Supergraph schema
+    #[clap(long)]
+    supergraph: PathBuf,
+    /// Router configuration whose cost_map entries are kept as they are
+    #[clap(long)]
+    config: Option<PathBuf>,
+    /// File to write the cost_map to, instead of stdout
+    #[clap(long)]
+    output: Option<PathBuf>,
+    #[clap(flatten)]
+    heuristics: Heuristics,
+}
+
+/// Weights suggested for fields, added up for the field's traits.
+#[derive(Args, Debug, Clone)]
+pub struct Heuristics {
+    /// Weight of fields returning scalars and enums
+    #[clap(long, default_value_t = 0)]
+    leaf_weight: usize,
+    /// Weight of fields returning objects, interfaces and unions
+    #[clap(long, default_value_t = 1)]
+    composite_weight: usize,
+    /// Added for fields returning lists
+    #[clap(long, default_value_t = 5)]
+    list_weight: usize,
+    /// Added for fields taking arguments
+    #[clap(long, default_value_t = 2)]
+    arguments_weight: usize,
+    /// Added for fields resolved by another subgraph than their parent type
+    /// (per `@join__field`), which need a separate fetch
+    #[clap(long, default_value_t = 10)]
+    subgraph_weight: usize,
+}
+
+impl Default for Heuristics {
+    /// The command line defaults.
+    fn default() -> Self {
+        #[derive(Parser)]
+        struct Defaults {
+            #[clap(flatten)]
+            heuristics: Heuristics,
+        }
+
+        Defaults::parse_from(["cost-map"]).heuristics
+    }
+}
+
+#[derive(Serialize)]
+struct Output<'a> {
+    cost_map: &'a BTreeMap<String, usize>,
+}
+
+impl CostMapArgs {
+    pub fn execute(&self) -> Result<()> {
+        let supergraph = std::fs::read_to_string(&self.supergraph)
+            .with_context(|| format!("could not read {}", self.supergraph.display()))?;
+        let config = match &self.config {
+            Some(path) => RouterConfig::read(path)?,
+            None => RouterConfig::default(),
+        };
+
+        let mut cost_map = generate(&supergraph, &self.heuristics);
+        if let Some(existing) = config.cost.map(|cost| cost.cost_map) {
+            for (coordinate, weight) in existing {
+                if !cost_map.contains_key(&coordinate) {
+                    eprintln!("keeping {}, which isn't in the supergraph", coordinate);
+                }
+                cost_map.insert(coordinate, weight);
+            }
+        }
+
+        let yaml = serde_yaml::to_string(&Output {
+            cost_map: &cost_map,
+        })?;
+        match &self.output {
+            Some(path) => std::fs::write(path, yaml)
+                .with_context(|| format!("could not write {}", path.display()))?,
+            None => print!("{}", yaml),
+        }
+
+        Ok(())
+    }
+}
+
+/// Suggests a weight for every field of the supergraph's object and interface
+/// types, except those already weighted with `@cost`.
+pub fn generate(supergraph: &str, heuristics: &Heuristics) -> BTreeMap<String, usize> {
+    let compiler = ApolloCompiler::new(supergraph);
+
+    let composite_types: HashSet<String> = compiler
+        .object_types()
+        .iter()
+        .map(|ty| ty.name().to_string())
+        .chain(compiler.interfaces().iter().map(|ty| ty.name().to_string()))
+        .chain(compiler.unions().iter().map(|ty| ty.name().to_string()))
+        .collect();
+
+    let types = compiler
+        .object_types()
+        .iter()
+        .map(|ty| {
+            (
+                ty.name().to_string(),
+                ty.directives().to_vec(),
+                ty.fields_definition().to_vec(),
+            )
+        })
+        .chain(compiler.interfaces().iter().map(|ty| {
+            (
+                ty.name().to_string(),
+                ty.directives().to_vec(),
+                ty.fields_definition().to_vec(),
+            )
+        }))
+        .collect::<Vec<_>>();
+
+    let mut cost_map = BTreeMap::new();
+    for (type_name, directives, fields) in types {
+        if is_internal(&type_name) {
+            continue;
+        }
+
+        // the subgraphs that can resolve the type's fields without another
+        // fetch: its owner in Federation 1, and every subgraph defining it
+        // in Federation 2, which has no owners
+        let type_graphs = if ROOT_TYPES.contains(&type_name.as_str()) {
+            None
+        } else {
+            let owner = graphs(&directives, "join__owner");
+            if owner.is_empty() {
+                Some(graphs(&directives, "join__type"))
+            } else {
+                Some(owner)
+            }
+        };
+
+        for field in fields {
+            if is_internal(field.name())
+                || field
+                    .directives()
+                    .iter()
+                    .any(|directive| directive.name() == "cost")
+            {
+                continue;
+            }
+
+            cost_map.insert(
+                format!("{}.{}", type_name, field.name()),
+                weight(&field, type_graphs.as_deref(), &composite_types, heuristics),
+            );
+        }
+    }
+
+    cost_map
+}
+
+fn weight(
+    field: &FieldDefinition,
+    type_graphs: Option<&[String]>,
+    composite_types: &HashSet<String>,
+    heuristics: &Heuristics,
+) -> usize {
+    let mut weight = if composite_types.contains(&field.ty().name()) {
+        heuristics.composite_weight
+    } else {
+        heuristics.leaf_weight
+    };
+
+    if field.ty().is_list() {
+        weight += heuristics.list_weight;
+    }
+    if !field.arguments().input_values().is_empty() {
+        weight += heuristics.arguments_weight;
+    }
+
+    let field_graphs = graphs(field.directives(), "join__field");
+    if let Some(type_graphs) = type_graphs {
+        if !type_graphs.is_empty()
+            && !field_graphs.is_empty()
+            && !field_graphs.iter().any(|graph| type_graphs.contains(graph))
+        {
+            weight += heuristics.subgraph_weight;
+        }
+    }
+
+    weight
+}
+
+/// Values of the `graph` argument of every `directive_name` directive.
+fn graphs(directives: &[Directive], directive_name: &str) -> Vec<String> {
+    directives
+        .iter()
+        .filter(|directive| directive.name() == directive_name)
+        .filter_map(|directive| {
+            directive
+                .arguments()
+                .iter()
+                .find(|arg| arg.name() == "graph")
+                .and_then(|arg| arg.value().to_json())
+                .and_then(|value| value.as_str().map(str::to_string))
+        })
+        .collect()
+}
+
+/// Introspection and federation types and fields, which clients don't select
+/// or which the router handles itself.
+fn is_internal(name: &str) -> bool {
+    name.starts_with("__")
+        || name.starts_with("join__")
+        || name.starts_with("link__")
+        || name.starts_with("core__")
+        || name == "_entities"
+        || name == "_service"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, Heuristics};
+
+    #[test]
+    fn heuristics() {
+        let cost_map = generate(
+            r#"
+            enum join__Graph { PRODUCTS @join__graph(name: "products" url: "") REVIEWS @join__graph(name: "reviews" url: "") }
+            type Query {
+              topProducts(first: Int = 5): [Product] @join__field(graph: PRODUCTS)
+              me: User @join__field(graph: REVIEWS)
+            }
+            type Product @join__owner(graph: PRODUCTS) @join__type(graph: PRODUCTS, key: "upc") @join__type(graph: REVIEWS, key: "upc") {
+              upc: String! @join__field(graph: PRODUCTS)
+              name: String @join__field(graph: PRODUCTS)
+              price: Int @cost(weight: 3)
+              reviews: [Review] @join__field(graph: REVIEWS)
+            }
+            type Review @join__owner(graph: REVIEWS) @join__type(graph: REVIEWS, key: "id") {
+              id: ID!
+              body: String
+            }
+            type User @join__type(graph: REVIEWS, key: "id") {
+              id: ID!
+            }
+            "#,
+            &Heuristics::default(),
+        );
+
+        // composite + list + arguments
+        assert_eq!(cost_map.get("Query.topProducts"), Some(&8));
+        assert_eq!(cost_map.get("Query.me"), Some(&1));
+        assert_eq!(cost_map.get("Product.name"), Some(&0));
+        // composite + list + another subgraph
+        assert_eq!(cost_map.get("Product.reviews"), Some(&16));
+        assert_eq!(cost_map.get("Review.body"), Some(&0));
+        // weighted in the schema already
+        assert_eq!(cost_map.get("Product.price"), None);
+        assert!(!cost_map
+            .keys()
+            .any(|coordinate| coordinate.starts_with("join__")));
+    }
+
+    #[test]
+    fn federation_2() {
+        let cost_map = generate(
+            r#"
+            enum join__Graph { PRODUCTS @join__graph(name: "products" url: "") REVIEWS @join__graph(name: "reviews" url: "") }
+            type Query {
+              topProducts: [Product] @join__field(graph: PRODUCTS)
+            }
+            type Product @join__type(graph: PRODUCTS, key: "upc") @join__type(graph: REVIEWS, key: "upc") {
+              upc: String! @join__field(graph: PRODUCTS) @join__field(graph: REVIEWS)
+              name: String @join__field(graph: PRODUCTS)
+              reviews: [Review] @join__field(graph: REVIEWS)
+            }
+            type Review @join__type(graph: REVIEWS) {
+              body: String
+            }
+            "#,
+            &Heuristics::default(),
+        );
+
+        // either subgraph defining Product resolves its fields
+        assert_eq!(cost_map.get("Product.name"), Some(&0));
+        assert_eq!(cost_map.get("Product.reviews"), Some(&6));
+    }
+
+    #[test]
+    fn default_heuristics() {
+        let heuristics = Heuristics::default();
+        assert_eq!(heuristics.composite_weight, 1);
+        assert_eq!(heuristics.subgraph_weight, 10);
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use operation_analysis::operation_cost::Cost;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::config::RouterConfig;
+use crate::cost::{Analyzer, Format};
+
+/// Calculate the cost and depth of many operations, to see how the
+/// configured limits would treat them
+#[derive(Args, Debug)]
+pub struct CostReportArgs {
+    /// Supergraph schema
+    #[clap(long)]
+    supergraph: PathBuf,
+    /// Router configuration to read the plugins' settings from
+    #[clap(long, default_value = "router.yaml")]
+    config: PathBuf,
+    /// Directory of .graphql files, persisted query manifest (.json) or log
+    /// of GraphQL requests, one per line (.jsonl)
+    #[clap(long)]
+    operations: PathBuf,
+    /// Number of coordinates to list
+    #[clap(long, default_value_t = 10)]
+    top: usize,
+    #[clap(long, value_enum, default_value = "human")]
+    format: Format,
+}
+
+/// An operation from the corpus.
+#[derive(Debug, PartialEq)]
+struct Operation {
+    /// Where the operation came from, e.g. its file or line.
+    source: String,
+    query: String,
+    operation_name: Option<String>,
+    variables: Map<String, JsonValue>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    pub operations: usize,
+    pub max_cost: Option<usize>,
+    pub depth_limit: Option<usize>,
+    /// `None` if no operation could be analysed.
+    pub cost: Option<Distribution>,
+    pub depth: Option<Distribution>,
+    /// Operations exceeding either limit, most expensive first.
+    pub rejected: Vec<Rejected>,
+    /// Cost contributed by each coordinate over all operations, most
+    /// expensive first.
+    pub top_coordinates: Vec<CoordinateCost>,
+    /// Operations that couldn't be analysed.
+    pub failed: Vec<Failed>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Distribution {
+    pub p50: usize,
+    pub p90: usize,
+    pub p95: usize,
+    pub p99: usize,
+    pub max: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Rejected {
+    pub source: String,
+    pub cost: usize,
+    pub depth: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CoordinateCost {
+    pub coordinate: String,
+    pub cost: Cost,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failed {
+    pub source: String,
+    pub error: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Manifest {
+    /// Apollo persisted query manifest.
+    Operations { operations: Vec<ManifestOperation> },
+    /// Map of IDs to query strings.
+    Map(BTreeMap<String, String>),
+}
+
+#[derive(Deserialize)]
+struct ManifestOperation {
+    id: String,
+    name: Option<String>,
+    body: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoggedRequest {
+    query: String,
+    operation_name: Option<String>,
+    variables: Option<Map<String, JsonValue>>,
+}
+
+impl CostReportArgs {
+    pub fn execute(&self) -> Result<()> {
+        let config = RouterConfig::read(&self.config)?;
+        let supergraph = std::fs::read_to_string(&self.supergraph)
+            .with_context(|| format!("could not read {}", self.supergraph.display()))?;
+        let operations = read_operations(&self.operations)?;
+
+        let analyzer = Analyzer::new(&config, &supergraph)?;
+        let summary = summarize(&config, &analyzer, &operations, self.top);
+
+        match self.format {
+            Format::Human => print_human(&summary),
+            Format::Json => println!("{}", serde_json::to_string_pretty(&summary)?),
+        }
+
+        Ok(())
+    }
+}
+
+fn read_operations(path: &Path) -> Result<Vec<Operation>> {
+    if path.is_dir() {
+        return read_directory(path);
+    }
+
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read {}", path.display()))?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("jsonl" | "ndjson") => parse_log(&contents),
+        _ => parse_manifest(&contents),
+    }
+    .with_context(|| format!("could not parse {}", path.display()))
+}
+
+/// Reads every `.graphql` and `.gql` file in `path`, each holding a single
+/// operation.
+fn read_directory(path: &Path) -> Result<Vec<Operation>> {
+    let mut files = std::fs::read_dir(path)
+        .with_context(|| format!("could not read {}", path.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    files.retain(|file| {
+        matches!(
+            file.extension().and_then(|extension| extension.to_str()),
+            Some("graphql" | "gql")
+        )
+    });
+    files.sort();
+
+    files
+        .into_iter()
+        .map(|file| {
+            Ok(Operation {
+                query: std::fs::read_to_string(&file)
+                    .with_context(|| format!("could not read {}", file.display()))?,
+                source: file.display().to_string(),
+                operation_name: None,
+                variables: Map::new(),
+            })
+        })
+        .collect()
+}
+
+fn parse_manifest(contents: &str) -> Result<Vec<Operation>> {
+    Ok(match serde_json::from_str(contents)? {
+        Manifest::Operations { operations } => operations
+            .into_iter()
+            .map(|operation| Operation {
+                source: operation.id,
+                query: operation.body,
+                operation_name: operation.name,
+                variables: Map::new(),
+            })
+            .collect(),
+        Manifest::Map(operations) => operations
+            .into_iter()
+            .map(|(id, query)| Operation {
+                source: id,
+                query,
+                operation_name: None,
+                variables: Map::new(),
+            })
+            .collect(),
+    })
+}
+
+/// Parses a log of GraphQL request bodies, one per line.
+fn parse_log(contents: &str) -> Result<Vec<Operation>> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let request: LoggedRequest = serde_json::from_str(line)
+                .with_context(|| format!("invalid request on line {}", i + 1))?;
+            Ok(Operation {
+                source: match &request.operation_name {
+                    Some(name) => format!("line {} ({})", i + 1, name),
+                    None => format!("line {}", i + 1),
+                },
+                query: request.query,
+                operation_name: request.operation_name,
+                variables: request.variables.unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+fn summarize(
+    config: &RouterConfig,
+    analyzer: &Analyzer,
+    operations: &[Operation],
+    top: usize,
+) -> Summary {
+    let mut costs = Vec::new();
+    let mut depths = Vec::new();
+    let mut rejected = Vec::new();
+    let mut coordinates: HashMap<String, Cost> = HashMap::new();
+    let mut failed = Vec::new();
+
+    for operation in operations {
+        let report = match analyzer.analyze(
+            &operation.query,
+            operation.operation_name.as_deref(),
+            &operation.variables,
+        ) {
+            Ok(report) => report,
+            Err(err) => {
+                failed.push(Failed {
+                    source: operation.source.clone(),
+                    error: err.to_string(),
+                });
+                continue;
+            }
+        };
+
+        costs.push(report.cost.get());
+        depths.push(report.depth);
+        if !report.pass {
+            rejected.push(Rejected {
+                source: operation.source.clone(),
+                cost: report.cost.get(),
+                depth: report.depth,
+            });
+        }
+        for (coordinate, cost) in report.coordinates {
+            *coordinates
+                .entry(coordinate)
+                .or_insert_with(|| Cost::new(0)) += cost;
+        }
+    }
+
+    rejected.sort_by(|a, b| b.cost.cmp(&a.cost).then_with(|| b.depth.cmp(&a.depth)));
+
+    let mut top_coordinates: Vec<_> = coordinates
+        .into_iter()
+        .map(|(coordinate, cost)| CoordinateCost { coordinate, cost })
+        .collect();
+    top_coordinates.sort_by(|a, b| {
+        b.cost
+            .cmp(&a.cost)
+            .then_with(|| a.coordinate.cmp(&b.coordinate))
+    });
+    top_coordinates.truncate(top);
+
+    Summary {
+        operations: operations.len(),
+        max_cost: config.max_cost(),
+        depth_limit: config.depth_limit(),
+        cost: distribution(costs),
+        depth: distribution(depths),
+        rejected,
+        top_coordinates,
+        failed,
+    }
+}
+
+/// Nearest-rank percentiles of `values`.
+fn distribution(mut values: Vec<usize>) -> Option<Distribution> {
+    values.sort_unstable();
+    let max = *values.last()?;
+    let percentile = |p: usize| {
+        let rank = (p * values.len() + 99) / 100;
+        values[rank.max(1) - 1]
+    };
+
+    Some(Distribution {
+        p50: percentile(50),
+        p90: percentile(90),
+        p95: percentile(95),
+        p99: percentile(99),
+        max,
+    })
+}
+
+fn print_human(summary: &Summary) {
+    let limit = |limit: Option<usize>| match limit {
+        Some(limit) => limit.to_string(),
+        None => "-".to_string(),
+    };
+
+    println!(
+        "operations: {} ({} failed)",
+        summary.operations,
+        summary.failed.len()
+    );
+    println!();
+
+    println!(
+        "{:6}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}",
+        "", "p50", "p90", "p95", "p99", "max", "limit"
+    );
+    for (name, distribution, limit_value) in [
+        ("cost", &summary.cost, summary.max_cost),
+        ("depth", &summary.depth, summary.depth_limit),
+    ] {
+        if let Some(distribution) = distribution {
+            println!(
+                "{:6}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}",
+                name,
+                distribution.p50,
+                distribution.p90,
+                distribution.p95,
+                distribution.p99,
+                distribution.max,
+                limit(limit_value),
+            );
+        }
+    }
+    println!();
+
+    println!("rejected: {}", summary.rejected.len());
+    for rejected in &summary.rejected {
+        println!(
+            "  {}  cost {}  depth {}",
+            rejected.source, rejected.cost, rejected.depth
+        );
+    }
+    println!();
+
+    println!("top coordinates:");
+    let width = summary
+        .top_coordinates
+        .iter()
+        .map(|coordinate| coordinate.coordinate.len())
+        .max()
+        .unwrap_or(0);
+    for coordinate in &summary.top_coordinates {
+        println!(
+            "  {:width$}  {:>10}",
+            coordinate.coordinate,
+            coordinate.cost.to_string(),
+            width = width,
+        );
+    }
+
+    if !summary.failed.is_empty() {
+        println!();
+        println!("failed:");
+        for failed in &summary.failed {
+            println!("  {}: {}", failed.source, failed.error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use operation_analysis::operation_cost::Cost;
+    use serde_json::{json, Map};
+
+    use super::{
+        distribution, parse_log, parse_manifest, summarize, CoordinateCost, Distribution, Operation,
+    };
+    use crate::config::RouterConfig;
+    use crate::cost::Analyzer;
+
+    fn operation(source: &str, query: &str) -> Operation {
+        Operation {
+            source: source.to_string(),
+            query: query.to_string(),
+            operation_name: None,
+            variables: Map::new(),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_manifest(
+                r#"{
+                  "format": "apollo-persisted-query-manifest",
+                  "version": 1,
+                  "operations": [{ "id": "abc", "name": "A", "type": "query", "body": "query A { a }" }]
+                }"#
+            )
+            .unwrap(),
+            vec![Operation {
+                operation_name: Some("A".to_string()),
+                ..operation("abc", "query A { a }")
+            }]
+        );
+        assert_eq!(
+            parse_manifest(r#"{ "abc": "{ a }" }"#).unwrap(),
+            vec![operation("abc", "{ a }")]
+        );
+
+        let operations = parse_log(
+            r#"{"query": "{ a }"}
+
+{"query": "query B($n: Int) { b(n: $n) }", "operationName": "B", "variables": {"n": 2}}"#,
+        )
+        .unwrap();
+        assert_eq!(operations[0], operation("line 1", "{ a }"));
+        assert_eq!(operations[1].source, "line 3 (B)");
+        assert_eq!(operations[1].variables.get("n"), Some(&json!(2)));
+        assert!(parse_log("{ a }").is_err());
+    }
+
+    #[test]
+    fn percentiles() {
+        assert_eq!(distribution(vec![]), None);
+        assert_eq!(
+            distribution((1..=200).rev().collect()),
+            Some(Distribution {
+                p50: 100,
+                p90: 180,
+                p95: 190,
+                p99: 198,
+                max: 200,
+            })
+        );
+        assert_eq!(
+            distribution(vec![7]),
+            Some(Distribution {
+                p50: 7,
+                p90: 7,
+                p95: 7,
+                p99: 7,
+                max: 7,
+            })
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let config = RouterConfig::parse(
+            "
+plugins:
+  apollosolutions.basic_operation_cost:
+    cost_map:
+      Query.a: 3
+    max_cost: 5
+",
+        )
+        .unwrap();
+        let analyzer = Analyzer::new(
+            &config,
+            "type Query { a(first: Int): [A] b: String } type A { c: String }",
+        )
+        .unwrap();
+
+        let summary = summarize(
+            &config,
+            &analyzer,
+            &[
+                operation("small", "{ b }"),
+                operation("large", "{ a(first: 4) { c } }"),
+                operation("broken", "fragment F on Query { b }"),
+                operation("medium", "{ a(first: 1) { c } }"),
+            ],
+            2,
+        );
+
+        assert_eq!(summary.operations, 4);
+        assert_eq!(summary.max_cost, Some(5));
+        assert_eq!(summary.cost.unwrap().max, 7);
+        assert_eq!(summary.rejected.len(), 1);
+        assert_eq!(summary.rejected[0].source, "large");
+        assert_eq!(
+            summary.top_coordinates,
+            vec![
+                CoordinateCost {
+                    coordinate: "Query.a".to_string(),
+                    cost: Cost::new(6),
+                },
+                CoordinateCost {
+                    coordinate: "A.c".to_string(),
+                    cost: Cost::new(5),
+                },
+            ]
+        );
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].source, "broken");
+
+        // the limits are reported even if no operation could be analysed
+        let summary = summarize(
+            &config,
+            &analyzer,
+            &[operation("broken", "fragment F on Query { b }")],
+            2,
+        );
+        assert_eq!(summary.max_cost, Some(5));
+        assert!(summary.cost.is_none());
+    }
+}
@@ -1,8 +1,17 @@
+mod config;
+mod cost;
+mod cost_map;
+mod cost_report;
+
 use anyhow::Result;
 use apollo_router_scaffold::RouterAction;
 use clap::Parser;
 use clap::Subcommand;
 
+use cost::CostArgs;
+use cost_map::CostMapArgs;
+use cost_report::CostReportArgs;
+
 #[derive(Parser, Debug)]
 struct Args {
     #[clap(subcommand)]
@@ -22,12 +31,18 @@ enum Action {
         #[clap(subcommand)]
         action: RouterAction,
     },
+    Cost(CostArgs),
+    CostMap(CostMapArgs),
+    CostReport(CostReportArgs),
 }
 
 impl Action {
     fn execute(&self) -> Result<()> {
         match self {
             Action::Router { action } => action.execute(),
+            Action::Cost(args) => args.execute(),
+            Action::CostMap(args) => args.execute(),
+            Action::CostReport(args) => args.execute(),
         }
     }
 }